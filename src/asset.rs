@@ -0,0 +1,126 @@
+use std::ops::Range;
+
+use flate2::Crc;
+use relative_path::{RelativePath, RelativePathBuf};
+
+/// Marks where the url of an asset goes, until all files are generated
+const MARKER: &str = "\u{1}asset:";
+
+/// Placeholder for the url of the asset at index
+pub(crate) fn placeholder(index: usize) -> String {
+    format!("{MARKER}{index}\u{1}")
+}
+
+/// Path with the hash of the content in the name, like `style.3f2a9c1d.css`
+pub(crate) fn fingerprint(path: &RelativePath, content: &[u8]) -> RelativePathBuf {
+    let mut crc = Crc::new();
+    crc.update(content);
+    let hash = format!("{:08x}", crc.sum());
+
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => path.with_file_name(format!("{stem}.{hash}.{ext}")),
+        (Some(stem), None) => path.with_file_name(format!("{stem}.{hash}")),
+        _ => path.to_relative_path_buf(),
+    }
+}
+
+/// Placeholders in the content, with where they are and the index of their asset
+fn find_placeholders(content: &[u8]) -> Vec<(Range<usize>, usize)> {
+    let marker = MARKER.as_bytes();
+    let mut found = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = content[pos..]
+        .windows(marker.len())
+        .position(|x| x == marker)
+    {
+        // index, up to the closing marker
+        let start = pos + start;
+        let digits = &content[start + marker.len()..];
+        let end = digits
+            .iter()
+            .position(|x| !x.is_ascii_digit())
+            .unwrap_or(digits.len());
+        let index = str::from_utf8(&digits[..end])
+            .ok()
+            .and_then(|x| x.parse::<usize>().ok())
+            .filter(|_| digits.get(end) == Some(&1));
+
+        // not a placeholder, skip the marker
+        pos = start + marker.len();
+        if let Some(index) = index {
+            pos += end + 1;
+            found.push((start..pos, index));
+        }
+    }
+    found
+}
+
+/// Indices of the assets the content refers to
+pub(crate) fn referenced(content: &[u8]) -> Vec<usize> {
+    find_placeholders(content)
+        .into_iter()
+        .map(|x| x.1)
+        .collect()
+}
+
+/// Replace the placeholders with the urls of the assets, if there are any
+pub(crate) fn replace_placeholders(content: &[u8], urls: &[String]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut last = 0;
+    for (range, index) in find_placeholders(content) {
+        // unknown asset, keep it
+        let Some(url) = urls.get(index) else {
+            continue;
+        };
+        out.extend_from_slice(&content[last..range.start]);
+        out.extend_from_slice(url.as_bytes());
+        last = range.end;
+    }
+
+    // nothing replaced, keep the content as is
+    if last == 0 {
+        return None;
+    }
+    out.extend_from_slice(&content[last..]);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_in_name() {
+        let path = fingerprint(RelativePath::new("css/style.css"), b"body {}");
+        assert!(path.as_str().starts_with("css/style."));
+        assert!(path.as_str().ends_with(".css"));
+        assert_eq!(path.as_str().len(), "css/style.12345678.css".len());
+        assert_ne!(
+            path,
+            fingerprint(RelativePath::new("css/style.css"), b"p {}")
+        );
+        assert_eq!(
+            fingerprint(RelativePath::new("LICENSE"), b"").as_str(),
+            "LICENSE.00000000"
+        );
+    }
+
+    #[test]
+    fn replace() {
+        let urls = ["/a.1.css".to_string(), "/b.2.js".to_string()];
+        let content = format!(
+            "{} {} {} \u{1}asset:x {}",
+            placeholder(1),
+            placeholder(0),
+            placeholder(5),
+            placeholder(1)
+        );
+        assert_eq!(referenced(content.as_bytes()), [1, 0, 5, 1]);
+        assert_eq!(
+            String::from_utf8(replace_placeholders(content.as_bytes(), &urls).unwrap()).unwrap(),
+            format!("/b.2.js /a.1.css {} \u{1}asset:x /b.2.js", placeholder(5))
+        );
+        assert_eq!(replace_placeholders(b"no assets", &urls), None);
+        assert_eq!(replace_placeholders(placeholder(2).as_bytes(), &urls), None);
+    }
+}
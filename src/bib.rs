@@ -0,0 +1,705 @@
+use std::collections::BTreeMap;
+
+use mlua::Result;
+
+/// Entries from a bibtex file, and how to cite them
+pub(crate) struct Bibliography {
+    /// Entries by their lowercase key
+    entries: BTreeMap<String, Entry>,
+
+    /// How to show citations in the text
+    style: Style,
+}
+
+/// Entry in a bibliography
+struct Entry {
+    /// Key as written in the file
+    key: String,
+
+    /// Kind of entry, like `article` or `book`
+    kind: String,
+
+    /// Fields, with lowercase names
+    fields: BTreeMap<String, String>,
+}
+
+/// How to show citations in the text
+#[derive(Clone, Copy)]
+enum Style {
+    /// `[1]`
+    Numeric,
+
+    /// `(Knuth 1984)`
+    AuthorYear,
+}
+
+/// Citation of a single entry, like `@knuth84, p. 33`
+pub(crate) struct Citation {
+    key: String,
+    locator: Option<String>,
+}
+
+impl Bibliography {
+    /// Parse a bibtex file, with the style to cite with
+    pub(crate) fn parse(text: &str, name: &str, style: Option<&str>) -> Result<Self> {
+        let style = match style.unwrap_or("numeric") {
+            "numeric" => Style::Numeric,
+            "author-year" => Style::AuthorYear,
+            style => {
+                return Err(mlua::Error::external(format!(
+                    "Unknown citation style `{style}`, expected `numeric` or `author-year`"
+                )));
+            }
+        };
+
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            strings: BTreeMap::new(),
+        };
+        let mut entries = BTreeMap::new();
+        while let Some(entry) = parser
+            .entry()
+            .map_err(|e| mlua::Error::external(format!("{name}:{}: {e}", parser.line())))?
+        {
+            entries.insert(entry.key.to_lowercase(), entry);
+        }
+
+        Ok(Self { entries, style })
+    }
+
+    /// Whether the key is in the bibliography
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(&key.to_lowercase())
+    }
+
+    /// Render citations in the text, numbering the entries in the order they are first cited
+    pub(crate) fn cite(&self, citations: &[Citation], cited: &mut Vec<String>) -> String {
+        let parts = citations
+            .iter()
+            .map(|citation| {
+                let key = citation.key.to_lowercase();
+                let entry = &self.entries[&key];
+                let number = match cited.iter().position(|x| x == &key) {
+                    Some(index) => index + 1,
+                    None => {
+                        cited.push(key.clone());
+                        cited.len()
+                    }
+                };
+                let label = match self.style {
+                    Style::Numeric => number.to_string(),
+                    Style::AuthorYear => {
+                        format!("{} {}", short_authors(entry), field(entry, "year"))
+                    }
+                };
+                let locator = citation
+                    .locator
+                    .as_ref()
+                    .map(|x| format!(", {}", escape_html(x)))
+                    .unwrap_or_default();
+                format!(
+                    "<a href=\"#ref-{}\">{}</a>{locator}",
+                    escape_html(&entry.key),
+                    escape_html(label.trim())
+                )
+            })
+            .collect::<Vec<_>>();
+
+        match self.style {
+            Style::Numeric => format!("<span class=\"citation\">[{}]</span>", parts.join(", ")),
+            Style::AuthorYear => {
+                format!("<span class=\"citation\">({})</span>", parts.join("; "))
+            }
+        }
+    }
+
+    /// Render a numbered list of the cited entries
+    pub(crate) fn render(&self, cited: &[String]) -> String {
+        let mut out = String::from("<section class=\"bibliography\">\n<ol>\n");
+        for key in cited {
+            let entry = &self.entries[key];
+            out.push_str(&format!("<li id=\"ref-{}\">", escape_html(&entry.key)));
+
+            // authors and year
+            let authors = match authors(entry).as_slice() {
+                [] => String::new(),
+                [one] => one.clone(),
+                [rest @ .., last] => format!("{} and {last}", rest.join(", ")),
+            };
+            if !authors.is_empty() {
+                out.push_str(&format!("{}. ", escape_html(&authors)));
+            }
+            if let Some(year) = entry.fields.get("year") {
+                out.push_str(&format!("{}. ", escape_html(year)));
+            }
+
+            // title, books are in italics
+            if let Some(title) = entry.fields.get("title") {
+                if ["book", "phdthesis", "mastersthesis"].contains(&entry.kind.as_str()) {
+                    out.push_str(&format!("<em>{}</em>. ", escape_html(title)));
+                } else {
+                    out.push_str(&format!("{}. ", escape_html(title)));
+                }
+            }
+
+            // where it was published
+            if let Some(container) = ["journal", "booktitle"]
+                .iter()
+                .find_map(|x| entry.fields.get(*x))
+            {
+                out.push_str(&format!("<em>{}</em>", escape_html(container)));
+                if let Some(volume) = entry.fields.get("volume") {
+                    out.push_str(&format!(" {}", escape_html(volume)));
+                }
+                if let Some(number) = entry.fields.get("number") {
+                    out.push_str(&format!("({})", escape_html(number)));
+                }
+                if let Some(pages) = entry.fields.get("pages") {
+                    out.push_str(&format!(", {}", escape_html(pages)));
+                }
+                out.push_str(". ");
+            }
+            if let Some(publisher) = ["publisher", "school", "institution", "howpublished"]
+                .iter()
+                .find_map(|x| entry.fields.get(*x))
+            {
+                out.push_str(&format!("{}. ", escape_html(publisher)));
+            }
+
+            // link, if any
+            if let Some(doi) = entry.fields.get("doi") {
+                let doi = escape_html(doi);
+                out.push_str(&format!("<a href=\"https://doi.org/{doi}\">doi:{doi}</a>"));
+            } else if let Some(url) = entry.fields.get("url") {
+                let url = escape_html(url);
+                out.push_str(&format!("<a href=\"{url}\">{url}</a>"));
+            }
+
+            // no trailing space
+            let trimmed = out.trim_end().len();
+            out.truncate(trimmed);
+            out.push_str("</li>\n");
+        }
+        out.push_str("</ol>\n</section>\n");
+        out
+    }
+}
+
+/// Parse citations like `@knuth84; @lamport94, p. 33`
+/// Returns none if this isn't a citation
+pub(crate) fn parse_citations(text: &str) -> Option<Vec<Citation>> {
+    text.split(';')
+        .map(|part| {
+            let part = part.trim().strip_prefix('@')?;
+            let (key, locator) = part
+                .split_once(',')
+                .map(|(x, y)| (x.trim(), Some(y.trim().to_string())))
+                .unwrap_or((part.trim(), None));
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return None;
+            }
+            Some(Citation {
+                key: key.to_string(),
+                locator: locator.filter(|x| !x.is_empty()),
+            })
+        })
+        .collect()
+}
+
+impl Citation {
+    /// Key of the cited entry
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Field of an entry, or an empty string if it's not there
+fn field<'a>(entry: &'a Entry, name: &str) -> &'a str {
+    entry.fields.get(name).map(String::as_str).unwrap_or("")
+}
+
+/// Authors, or editors if there are none, as `First Last`
+fn authors(entry: &Entry) -> Vec<String> {
+    let names = entry
+        .fields
+        .get("author")
+        .or(entry.fields.get("editor"))
+        .map(String::as_str)
+        .unwrap_or("");
+    names
+        .split(" and ")
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| match x.split_once(',') {
+            Some((last, first)) => format!("{} {}", first.trim(), last.trim()),
+            None => x.to_string(),
+        })
+        .collect()
+}
+
+/// Last names of the authors, shortened to `et al.` if there are more than two
+fn short_authors(entry: &Entry) -> String {
+    let last = authors(entry)
+        .iter()
+        .map(|x| x.rsplit(' ').next().unwrap_or(x).to_string())
+        .collect::<Vec<_>>();
+    match last.as_slice() {
+        [] => String::new(),
+        [one] => one.clone(),
+        [one, two] => format!("{one} and {two}"),
+        [one, ..] => format!("{one} et al."),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+
+    /// Strings defined with `@string`
+    strings: BTreeMap<String, String>,
+}
+
+impl Parser<'_> {
+    /// Line we are at
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|x| x.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> std::result::Result<(), String> {
+        self.whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            Ok(())
+        } else {
+            Err(format!("Expected `{c}`"))
+        }
+    }
+
+    /// Name of an entry type, field or string
+    fn ident(&mut self) -> std::result::Result<String, String> {
+        self.whitespace();
+        let start = self.pos;
+        while let Some(c) = self
+            .peek()
+            .filter(|x| !x.is_whitespace() && !"{}(),=#\"".contains(*x))
+        {
+            self.pos += c.len_utf8();
+        }
+        if self.pos == start {
+            return Err("Expected a name".to_string());
+        }
+        Ok(self.text[start..self.pos].to_string())
+    }
+
+    /// Next entry, skipping comments, strings and preambles
+    fn entry(&mut self) -> std::result::Result<Option<Entry>, String> {
+        loop {
+            // anything outside of an entry is a comment
+            let Some(start) = self.text[self.pos..].find('@') else {
+                self.pos = self.text.len();
+                return Ok(None);
+            };
+            self.pos += start + 1;
+
+            let kind = self.ident()?.to_lowercase();
+            self.whitespace();
+            let close = match self.peek() {
+                Some('{') => '}',
+                Some('(') => ')',
+                _ => return Err(format!("Expected `{{` after `@{kind}`")),
+            };
+            self.pos += 1;
+
+            match kind.as_str() {
+                "comment" | "preamble" => {
+                    self.pos -= 1;
+                    self.skip_group()?;
+                }
+                "string" => {
+                    let name = self.ident()?.to_lowercase();
+                    self.expect('=')?;
+                    let value = self.value()?;
+                    self.strings.insert(name, value);
+                    self.expect(close)?;
+                }
+                _ => {
+                    let key = self.ident()?;
+                    let mut fields = BTreeMap::new();
+                    loop {
+                        self.whitespace();
+                        match self.peek() {
+                            Some(',') => self.pos += 1,
+                            Some(c) if c == close => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return Err(format!("Expected `,` or `{close}` in `{key}`")),
+                        }
+
+                        // trailing comma
+                        self.whitespace();
+                        if self.peek() == Some(close) {
+                            continue;
+                        }
+
+                        let name = self.ident()?.to_lowercase();
+                        self.expect('=')?;
+                        fields.insert(name, self.value()?);
+                    }
+
+                    return Ok(Some(Entry { key, kind, fields }));
+                }
+            }
+        }
+    }
+
+    /// Skip a `{...}` or `(...)` group
+    fn skip_group(&mut self) -> std::result::Result<(), String> {
+        let (open, close) = match self.peek() {
+            Some('(') => ('(', ')'),
+            _ => ('{', '}'),
+        };
+        let mut depth = 0;
+        for (i, c) in self.text[self.pos..].char_indices() {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    self.pos += i + 1;
+                    return Ok(());
+                }
+            }
+        }
+        Err(format!("Unclosed `{open}`"))
+    }
+
+    /// Field value, parts joined with `#`
+    fn value(&mut self) -> std::result::Result<String, String> {
+        let mut value = String::new();
+        loop {
+            self.whitespace();
+            match self.peek() {
+                Some('{') => {
+                    let start = self.pos + 1;
+                    self.skip_group()?;
+                    value.push_str(&latex(&self.text[start..self.pos - 1]));
+                }
+                Some('"') => {
+                    // quotes can contain braces, and quotes inside them
+                    let start = self.pos + 1;
+                    let mut depth = 0;
+                    let mut end = None;
+                    for (i, c) in self.text[start..].char_indices() {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            '"' if depth == 0 => {
+                                end = Some(start + i);
+                                break;
+                            }
+                            _ => (),
+                        }
+                    }
+                    let end = end.ok_or("Unclosed `\"`")?;
+                    value.push_str(&latex(&self.text[start..end]));
+                    self.pos = end + 1;
+                }
+                Some(c) if c.is_ascii_digit() => {
+                    let start = self.pos;
+                    while self.peek().is_some_and(|x| x.is_ascii_digit()) {
+                        self.pos += 1;
+                    }
+                    value.push_str(&self.text[start..self.pos]);
+                }
+                _ => {
+                    let name = self.ident()?.to_lowercase();
+                    let string = self
+                        .strings
+                        .get(&name)
+                        .cloned()
+                        .or_else(|| month(&name).map(str::to_string))
+                        .ok_or_else(|| format!("Unknown string `{name}`"))?;
+                    value.push_str(&string);
+                }
+            }
+
+            self.whitespace();
+            if self.peek() == Some('#') {
+                self.pos += 1;
+            } else {
+                return Ok(value.trim().to_string());
+            }
+        }
+    }
+}
+
+/// Month strings that are always defined
+fn month(name: &str) -> Option<&'static str> {
+    let months = [
+        ("jan", "January"),
+        ("feb", "February"),
+        ("mar", "March"),
+        ("apr", "April"),
+        ("may", "May"),
+        ("jun", "June"),
+        ("jul", "July"),
+        ("aug", "August"),
+        ("sep", "September"),
+        ("oct", "October"),
+        ("nov", "November"),
+        ("dec", "December"),
+    ];
+    months.iter().find(|x| x.0 == name).map(|x| x.1)
+}
+
+/// Turn latex in a value into plain text
+fn latex(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => (),
+            '~' => out.push('\u{a0}'),
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                if chars.peek() == Some(&'-') {
+                    chars.next();
+                    out.push('—');
+                } else {
+                    out.push('–');
+                }
+            }
+            '\\' => {
+                // command name, or a single symbol
+                let mut command = String::new();
+                while let Some(c) = chars.peek().filter(|x| x.is_ascii_alphabetic()) {
+                    command.push(*c);
+                    chars.next();
+                }
+                if command.is_empty()
+                    && let Some(c) = chars.next()
+                {
+                    command.push(c);
+                }
+
+                // accents go on the next letter
+                let accent = match command.as_str() {
+                    "\"" => Some('\u{308}'),
+                    "'" => Some('\u{301}'),
+                    "`" => Some('\u{300}'),
+                    "^" => Some('\u{302}'),
+                    "~" => Some('\u{303}'),
+                    "=" => Some('\u{304}'),
+                    "." => Some('\u{307}'),
+                    "c" => Some('\u{327}'),
+                    "v" => Some('\u{30c}'),
+                    "u" => Some('\u{306}'),
+                    "H" => Some('\u{30b}'),
+                    _ => None,
+                };
+                if let Some(accent) = accent {
+                    while chars.peek().is_some_and(|x| x.is_whitespace() || *x == '{') {
+                        chars.next();
+                    }
+                    if let Some(c) = chars.next() {
+                        out.push(c);
+                        out.push(accent);
+                    }
+                    continue;
+                }
+
+                match command.as_str() {
+                    "TeX" | "LaTeX" | "BibTeX" => out.push_str(&command),
+                    "\\" => out.push(' '),
+                    "ss" => out.push('ß'),
+                    "o" => out.push('ø'),
+                    "O" => out.push('Ø'),
+                    "aa" => out.push('å'),
+                    "AA" => out.push('Å'),
+                    "ae" => out.push('æ'),
+                    "AE" => out.push('Æ'),
+                    "l" => out.push('ł'),
+                    "L" => out.push('Ł'),
+                    "i" => out.push('ı'),
+                    // escaped symbols
+                    c if c.len() == 1 && !c.chars().all(|x| x.is_ascii_alphabetic()) => {
+                        out.push_str(c)
+                    }
+                    // other commands, keep their arguments
+                    _ => (),
+                }
+
+                // commands eat the space after them
+                if command.chars().all(|x| x.is_ascii_alphabetic()) {
+                    while chars.peek().is_some_and(|x| *x == ' ') {
+                        chars.next();
+                    }
+                }
+            }
+            c if c.is_whitespace() => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    for c in html.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIB: &str = r#"
+@string{ acm = "ACM Press" }
+@comment{ not an @entry{x, title = {y}} }
+Anything outside of an entry is a comment.
+
+@Book{Knuth84,
+  author = {Knuth, Donald E.},
+  title = {The {\TeX}book},
+  year = 1984,
+  publisher = acm # " and friends",
+  month = mar,
+}
+
+@article(lamport94,
+  author = "Lamport, Leslie and Doe, Jane and Roe, Richard",
+  title = "{LaTeX}: a document preparation system",
+  journal = {Journal}, volume = 3, number = {2}, pages = {1--10}, year = {1994},
+  doi = {10.1/x&y}
+)
+"#;
+
+    fn cite(bibliography: &Bibliography, text: &str, cited: &mut Vec<String>) -> String {
+        bibliography.cite(&parse_citations(text).unwrap(), cited)
+    }
+
+    #[test]
+    fn numeric() {
+        let bibliography = Bibliography::parse(BIB, "refs.bib", None).unwrap();
+        assert!(bibliography.contains("KNUTH84"));
+        assert!(!bibliography.contains("x"));
+
+        // numbered in the order they are first cited
+        let mut cited = Vec::new();
+        assert_eq!(
+            cite(&bibliography, "@lamport94, p. 3; @KNUTH84", &mut cited),
+            concat!(
+                "<span class=\"citation\">[<a href=\"#ref-lamport94\">1</a>, p. 3, ",
+                "<a href=\"#ref-Knuth84\">2</a>]</span>"
+            )
+        );
+        assert_eq!(
+            cite(&bibliography, "@knuth84", &mut cited),
+            "<span class=\"citation\">[<a href=\"#ref-Knuth84\">2</a>]</span>"
+        );
+        assert_eq!(cited, ["lamport94", "knuth84"]);
+
+        assert_eq!(
+            bibliography.render(&cited),
+            concat!(
+                "<section class=\"bibliography\">\n<ol>\n",
+                "<li id=\"ref-lamport94\">Leslie Lamport, Jane Doe and Richard Roe. 1994. ",
+                "LaTeX: a document preparation system. <em>Journal</em> 3(2), 1–10. ",
+                "<a href=\"https://doi.org/10.1/x&amp;y\">doi:10.1/x&amp;y</a></li>\n",
+                "<li id=\"ref-Knuth84\">Donald E. Knuth. 1984. <em>The TeXbook</em>. ",
+                "ACM Press and friends.</li>\n",
+                "</ol>\n</section>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn author_year() {
+        let bibliography = Bibliography::parse(BIB, "refs.bib", Some("author-year")).unwrap();
+        assert_eq!(
+            cite(&bibliography, "@lamport94, p. 3; @knuth84", &mut Vec::new()),
+            concat!(
+                "<span class=\"citation\">(<a href=\"#ref-lamport94\">Lamport et al. 1994</a>",
+                ", p. 3; <a href=\"#ref-Knuth84\">Knuth 1984</a>)</span>"
+            )
+        );
+    }
+
+    #[test]
+    fn citations() {
+        let citations = parse_citations(" @a ; @b,  ").unwrap();
+        assert_eq!(
+            citations.iter().map(Citation::key).collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(citations[1].locator, None);
+        assert!(parse_citations("not a citation").is_none());
+        assert!(parse_citations("@a b").is_none());
+        assert!(parse_citations("@a; b").is_none());
+        assert!(parse_citations("@").is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text| {
+            Bibliography::parse(text, "refs.bib", None)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert!(
+            error("@book{x,\n  title = {a}\n  author = {b}}")
+                .contains("refs.bib:3: Expected `,` or `}` in `x`")
+        );
+        assert!(error("@misc{x, title = foo}").contains("Unknown string `foo`"));
+        assert!(error("@misc{x, title = {a}").contains("Expected `,` or `}`"));
+        assert!(error("@misc{x, title = \"a}").contains("Unclosed `\"`"));
+        assert!(
+            Bibliography::parse("", "refs.bib", Some("chicago"))
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("Unknown citation style `chicago`")
+        );
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(
+            latex("G{\\\"o}del and Caf\\'e"),
+            "Go\u{308}del and Cafe\u{301}"
+        );
+        assert_eq!(
+            latex("1--2---3 a~b 50\\% \\LaTeX{} rocks"),
+            "1–2—3 a\u{a0}b 50% LaTeX rocks"
+        );
+        assert_eq!(latex("Stra\\ss e,\n  \\emph{really}"), "Straße, really");
+        assert_eq!(month("sep"), Some("September"));
+        assert_eq!(month("sept"), None);
+    }
+}
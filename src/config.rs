@@ -0,0 +1,341 @@
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+use glob::Pattern;
+use mlua::{ErrorContext, ExternalResult, Result};
+use pulldown_cmark::Options;
+use relative_path::{Component, RelativePathBuf};
+
+use crate::markdown::extension;
+
+/// Settings from the `site.conf` file
+pub(crate) struct Config {
+    /// Where to output the site to when building
+    pub output: Option<RelativePathBuf>,
+
+    /// Whether files starting with a date are drafts until that date
+    pub scheduled: bool,
+
+    /// Script to run before processing
+    pub setup: Option<RelativePathBuf>,
+
+    /// Files to ignore
+    pub ignore: Vec<Pattern>,
+
+    /// Page to use as 404 when developing
+    pub not_found: Option<String>,
+
+    /// Whether to subset fonts
+    pub subset: bool,
+
+    /// Extra characters to include when subsetting
+    pub extra: String,
+
+    /// File with TeX macros to use in math
+    pub math_macros: Option<RelativePathBuf>,
+
+    /// Whether to compile templates to one function, for all files or those matching the glob
+    pub compile: Vec<(Option<Pattern>, bool)>,
+
+    /// Markdown extensions to turn on or off, for all files or those matching the glob
+    pub markdown: Vec<(Option<Pattern>, Options, bool)>,
+
+    /// Variables available in the `config` table
+    pub variables: BTreeMap<String, String>,
+
+    /// Variables per profile, these override the normal variables
+    pub profiles: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Options given on the command line
+#[derive(Clone, Default)]
+pub(crate) struct Flags {
+    /// Profile to build with
+    pub profile: Option<String>,
+
+    /// Variables set on the command line, these override the config file
+    pub defines: Vec<(String, String)>,
+
+    /// Whether site scripts are sandboxed
+    pub safe: bool,
+}
+
+impl Flags {
+    /// Read the flags from the arguments
+    pub(crate) fn from_args(pargs: &mut pico_args::Arguments) -> Result<Self> {
+        let profile = pargs
+            .opt_value_from_str(["-p", "--profile"])
+            .into_lua_err()
+            .context("Failed to parse arguments")?;
+
+        let defines = pargs
+            .values_from_str::<_, String>(["-d", "--define"])
+            .into_lua_err()
+            .context("Failed to parse arguments")?
+            .into_iter()
+            .map(|x| {
+                x.split_once('=')
+                    .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                    .ok_or_else(|| {
+                        mlua::Error::external(format!("Expected `--define key=value`, found `{x}`"))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let safe = pargs.contains("--safe");
+
+        Ok(Self {
+            profile,
+            defines,
+            safe,
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            output: None,
+            scheduled: false,
+            setup: None,
+            ignore: Vec::new(),
+            not_found: None,
+            subset: true,
+            extra: String::new(),
+            math_macros: None,
+            compile: Vec::new(),
+            markdown: Vec::new(),
+            variables: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file at the given path, or the default config if it does not exist
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text, &path.to_string_lossy()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+                .into_lua_err()
+                .with_context(|_| format!("Failed to read `{}`", path.to_string_lossy())),
+        }
+    }
+
+    /// Parse the config file
+    fn parse(text: &str, name: &str) -> Result<Self> {
+        let mut config = Self::default();
+        let mut section = String::new();
+
+        for (line, text) in text.lines().enumerate() {
+            let line = line + 1;
+
+            // remove comments, these start with a # at the start of the line or after whitespace
+            let text = if text.trim_start().starts_with('#') {
+                ""
+            } else {
+                text.split_once(" #")
+                    .or(text.split_once("\t#"))
+                    .map(|x| x.0)
+                    .unwrap_or(text)
+            }
+            .trim();
+
+            // empty line, skip
+            if text.is_empty() {
+                continue;
+            }
+
+            // section
+            if let Some(name) = text.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            // ignore section only has globs
+            if section == "ignore" {
+                config
+                    .ignore
+                    .push(Pattern::new(text).into_lua_err().with_context(|_| {
+                        format!("{name}:{line}: Failed to make glob pattern `{text}`")
+                    })?);
+                continue;
+            }
+
+            // key value pair
+            let (key, value) = text.split_once('=').ok_or_else(|| {
+                mlua::Error::external(format!("{name}:{line}: Expected `key = value`"))
+            })?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match (section.as_str(), key) {
+                ("build", "output") => config.output = Some(parse_output(value, name, line)?),
+                ("build", "scheduled") => config.scheduled = parse_bool(value, name, line)?,
+                ("build", "setup") => config.setup = Some(RelativePathBuf::from(value)),
+                ("dev", "not-found") => config.not_found = Some(value.to_string()),
+                ("font", "subset") => config.subset = parse_bool(value, name, line)?,
+                ("font", "extra") => config.extra.push_str(value),
+                ("math", "macros") => config.math_macros = Some(RelativePathBuf::from(value)),
+                ("template", "compile") => {
+                    config.compile.push((None, parse_bool(value, name, line)?))
+                }
+                (section, "compile") if section.starts_with("template.") => {
+                    let glob = section["template.".len()..].trim();
+                    let glob = Pattern::new(glob).into_lua_err().with_context(|_| {
+                        format!("{name}:{line}: Failed to make glob pattern `{glob}`")
+                    })?;
+                    config
+                        .compile
+                        .push((Some(glob), parse_bool(value, name, line)?));
+                }
+                ("markdown", key) => config.markdown.push((
+                    None,
+                    markdown_extension(key, name, line)?,
+                    parse_bool(value, name, line)?,
+                )),
+                (section, key) if section.starts_with("markdown.") => {
+                    let glob = section["markdown.".len()..].trim();
+                    let glob = Pattern::new(glob).into_lua_err().with_context(|_| {
+                        format!("{name}:{line}: Failed to make glob pattern `{glob}`")
+                    })?;
+                    config.markdown.push((
+                        Some(glob),
+                        markdown_extension(key, name, line)?,
+                        parse_bool(value, name, line)?,
+                    ));
+                }
+                ("config", key) => {
+                    config.variables.insert(key.to_string(), value.to_string());
+                }
+                (section, key) if section.starts_with("profile.") => {
+                    config
+                        .profiles
+                        .entry(section["profile.".len()..].trim().to_string())
+                        .or_default()
+                        .insert(key.to_string(), value.to_string());
+                }
+                ("", key) => {
+                    return Err(mlua::Error::external(format!(
+                        "{name}:{line}: Key `{key}` is not in a section"
+                    )));
+                }
+                (section, key) => {
+                    return Err(mlua::Error::external(format!(
+                        "{name}:{line}: Unknown key `{key}` in section `[{section}]`"
+                    )));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Get the variables for the `config` table, with the profile and flags applied
+    pub(crate) fn variables(&self, flags: &Flags) -> Result<BTreeMap<String, String>> {
+        let mut variables = self.variables.clone();
+
+        // profile overrides the defaults
+        if let Some(name) = &flags.profile {
+            let profile = self.profiles.get(name).ok_or_else(|| {
+                mlua::Error::external(format!(
+                    "Unknown profile `{name}`, expected a `[profile.{name}]` section in `site.conf`"
+                ))
+            })?;
+            variables.extend(profile.clone());
+        }
+
+        // flags override everything
+        variables.extend(flags.defines.iter().cloned());
+        Ok(variables)
+    }
+}
+
+/// Find a markdown extension
+fn markdown_extension(key: &str, name: &str, line: usize) -> Result<Options> {
+    extension(key).ok_or_else(|| {
+        mlua::Error::external(format!("{name}:{line}: Unknown markdown extension `{key}`"))
+    })
+}
+
+/// Parse the output directory, which has to be a directory inside the site
+/// It is cleared before building, so it can't be the site itself or outside of it
+fn parse_output(value: &str, name: &str, line: usize) -> Result<RelativePathBuf> {
+    let path = RelativePathBuf::from(value);
+    if value.is_empty()
+        || Path::new(value).has_root()
+        || Path::new(value).is_absolute()
+        || path.components().any(|x| x == Component::ParentDir)
+        || path.normalize().as_str().is_empty()
+    {
+        return Err(mlua::Error::external(format!(
+            "{name}:{line}: Output `{value}` has to be a directory inside the site"
+        )));
+    }
+
+    Ok(path.normalize())
+}
+
+/// Parse a boolean value
+fn parse_bool(value: &str, name: &str, line: usize) -> Result<bool> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(mlua::Error::external(format!(
+            "{name}:{line}: Expected `true` or `false`, found `{value}`"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_inside_site() {
+        let config = Config::parse("[build]\noutput = public/site/", "site.conf").unwrap();
+        assert_eq!(config.output.unwrap().as_str(), "public/site");
+    }
+
+    #[test]
+    fn profile_overrides_variables() {
+        let text = "[config]\nurl = local\ntitle = site\n[profile.production]\nurl = remote";
+        let config = Config::parse(text, "site.conf").unwrap();
+        let flags = Flags {
+            profile: Some("production".to_string()),
+            defines: vec![("title".to_string(), "other".to_string())],
+            safe: false,
+        };
+        let variables = config.variables(&flags).unwrap();
+        assert_eq!(variables["url"], "remote");
+        assert_eq!(variables["title"], "other");
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let config = Config::parse("[profile.production]\nurl = remote", "site.conf").unwrap();
+        let flags = Flags {
+            profile: Some("prod".to_string()),
+            ..Default::default()
+        };
+        assert!(config.variables(&flags).is_err());
+    }
+
+    #[test]
+    fn output_outside_site() {
+        for output in [
+            "",
+            ".",
+            "./",
+            "..",
+            "../public",
+            "public/../..",
+            "/tmp/public",
+        ] {
+            let text = format!("[build]\noutput = {output}");
+            assert!(
+                Config::parse(&text, "site.conf").is_err(),
+                "`{output}` should not be allowed"
+            );
+        }
+    }
+}
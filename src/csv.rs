@@ -0,0 +1,103 @@
+use csv::ReaderBuilder;
+use mlua::{Lua, Result, Table, Value};
+
+use crate::data::{create_array, line_col};
+
+/// Parse csv into a table of rows
+/// If there is a header, rows are tables with the header as keys, otherwise arrays
+pub(crate) fn parse_csv(
+    lua: &Lua,
+    text: &str,
+    name: &str,
+    separator: u8,
+    header: bool,
+) -> Result<Table> {
+    let rows = records(text, name, separator)?;
+    let mut rows = rows.into_iter();
+
+    // turn it into lua values
+    let mut values = Vec::new();
+    if header {
+        let Some((_, keys)) = rows.next() else {
+            return create_array(lua, values);
+        };
+
+        for (line, row) in rows {
+            if row.len() != keys.len() {
+                return Err(mlua::Error::external(format!(
+                    "{name}:{line}: Expected {} fields, but found {}",
+                    keys.len(),
+                    row.len()
+                )));
+            }
+
+            let table = lua.create_table()?;
+            for (key, field) in keys.iter().zip(row) {
+                table.raw_set(key.as_str(), field)?;
+            }
+            values.push(Value::Table(table));
+        }
+    } else {
+        for (_, row) in rows {
+            let row = row
+                .into_iter()
+                .map(|x| lua.create_string(x).map(Value::String))
+                .collect::<Result<Vec<Value>>>()?;
+            values.push(Value::Table(create_array(lua, row)?));
+        }
+    }
+
+    create_array(lua, values)
+}
+
+/// Split the text into records, with the line they start on
+fn records(text: &str, name: &str, separator: u8) -> Result<Vec<(usize, Vec<String>)>> {
+    ReaderBuilder::new()
+        .delimiter(separator)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes())
+        .into_records()
+        .map(|record| {
+            let record = record.map_err(|e| mlua::Error::external(format!("{name}: {e}")))?;
+            // records after a crlf can start at its newline
+            let start = record.position().map(|x| x.byte() as usize).unwrap_or(0);
+            let rest = text.get(start..).unwrap_or_default();
+            let (line, _) = line_col(text, text.len() - rest.trim_start_matches('\n').len());
+            Ok((line, record.iter().map(str::to_string).collect()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_fields() {
+        let rows = records("a,\"b,\"\"c\"\"\"\r\n\"multi\nline\",d\n", "data.csv", b',').unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1, vec!["a".to_string(), "b,\"c\"".to_string()]),
+                (2, vec!["multi\nline".to_string(), "d".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn separator() {
+        let rows = records("a;b\nc;d", "data.csv", b';').unwrap();
+        assert_eq!(rows[1], (2, vec!["c".to_string(), "d".to_string()]));
+    }
+
+    #[test]
+    fn header() {
+        let lua = Lua::new();
+        let rows = parse_csv(&lua, "name,age\nan,3\nbo,4", "data.csv", b',', true).unwrap();
+        assert_eq!(rows.raw_len(), 2);
+        let row: Table = rows.raw_get(2).unwrap();
+        assert_eq!(row.raw_get::<String>("name").unwrap(), "bo");
+        assert!(parse_csv(&lua, "name,age\nan", "data.csv", b',', true).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::ffi::c_void;
+
+use mlua::{Lua, Result, Table, Value};
+
+/// Get the metatable used to mark tables as arrays
+/// This allows turning empty tables back into arrays when serializing
+pub(crate) fn array_metatable(lua: &Lua) -> Result<Table> {
+    if let Some(metatable) = lua.named_registry_value::<Option<Table>>("slsg.array")? {
+        Ok(metatable)
+    } else {
+        let metatable = lua.create_table()?;
+        metatable.set("__name", "array")?;
+        lua.set_named_registry_value("slsg.array", &metatable)?;
+        Ok(metatable)
+    }
+}
+
+/// Make a table from the values, marked as an array
+/// Nil values become `null`, so the array has no holes
+pub(crate) fn create_array(lua: &Lua, values: Vec<Value>) -> Result<Table> {
+    let values = values
+        .into_iter()
+        .map(|x| if x.is_nil() { Value::NULL } else { x });
+    let table = lua.create_sequence_from(values)?;
+    table.set_metatable(Some(array_metatable(lua)?));
+    Ok(table)
+}
+
+/// Get the line and column, starting at 1, of the byte offset in the text
+pub(crate) fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line = before.chars().filter(|x| *x == '\n').count() + 1;
+    let column = before
+        .rsplit_once('\n')
+        .map(|x| x.1)
+        .unwrap_or(before)
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+/// Whether the table is marked as an array
+pub(crate) fn is_array(lua: &Lua, table: &Table) -> Result<bool> {
+    let array = array_metatable(lua)?;
+    Ok(table
+        .metatable()
+        .map(|x| x.to_pointer() == array.to_pointer())
+        .unwrap_or(false))
+}
+
+/// Contents of a table, to serialize it
+pub(crate) enum Entries {
+    /// Marked as array, or only has the keys 1 to n
+    Array(Vec<Value>),
+
+    /// Anything else, sorted by key
+    Map(Vec<(String, Value)>),
+}
+
+/// Get the contents of a table
+pub(crate) fn entries(lua: &Lua, table: &Table) -> Result<Entries> {
+    let pairs = table.pairs::<Value, Value>().collect::<Result<Vec<_>>>()?;
+    let len = table.raw_len();
+
+    // arrays
+    if is_array(lua, table)? || (len > 0 && pairs.len() == len) {
+        return (1..=len)
+            .map(|i| table.raw_get(i))
+            .collect::<Result<Vec<Value>>>()
+            .map(Entries::Array);
+    }
+
+    // maps, with the keys as strings
+    let mut map = pairs
+        .into_iter()
+        .map(|(key, value)| match key {
+            Value::String(key) => Ok((key.to_string_lossy(), value)),
+            Value::Integer(key) => Ok((key.to_string(), value)),
+            Value::Number(key) => Ok((key.to_string(), value)),
+            key => Err(mlua::Error::external(format!(
+                "Can't use a {} as key",
+                key.type_name()
+            ))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    map.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // keys like 1 and "1" become the same key
+    if let Some(pair) = map.windows(2).find(|x| x[0].0 == x[1].0) {
+        return Err(mlua::Error::external(format!(
+            "Table has the key `{}` both as number and as string",
+            pair[0].0
+        )));
+    }
+
+    Ok(Entries::Map(map))
+}
+
+/// Tables that are being serialized, to catch tables that contain themselves
+#[derive(Default)]
+pub(crate) struct Visited(Vec<*const c_void>);
+
+impl Visited {
+    /// Enter a table, fails if it's already being serialized
+    pub(crate) fn enter(&mut self, table: &Table) -> Result<()> {
+        let pointer = table.to_pointer();
+        if self.0.contains(&pointer) {
+            Err(mlua::Error::external(
+                "Can't serialize a table that contains itself",
+            ))
+        } else {
+            self.0.push(pointer);
+            Ok(())
+        }
+    }
+
+    /// Leave the last entered table
+    pub(crate) fn leave(&mut self) {
+        self.0.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_and_column() {
+        assert_eq!(line_col("ab\ncd", 0), (1, 1));
+        assert_eq!(line_col("ab\ncd", 4), (2, 2));
+        assert_eq!(line_col("é\nx", 1), (1, 1));
+        assert_eq!(line_col("ab", 10), (1, 3));
+    }
+
+    #[test]
+    fn arrays_have_no_holes() {
+        let lua = Lua::new();
+        let array = create_array(&lua, vec![Value::Nil, Value::Integer(1), Value::Nil]).unwrap();
+        assert_eq!(array.raw_len(), 3);
+        assert!(is_array(&lua, &array).unwrap());
+        assert!(array.raw_get::<Value>(1).unwrap().is_null());
+    }
+
+    #[test]
+    fn number_and_string_key() {
+        let lua = Lua::new();
+        let table = lua.create_table().unwrap();
+        table.raw_set(1, "number").unwrap();
+        table.raw_set("1", "string").unwrap();
+        table.raw_set("x", "other").unwrap();
+        assert!(entries(&lua, &table).is_err());
+    }
+}
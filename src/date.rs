@@ -0,0 +1,427 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Year, month and day from the days since the unix epoch
+/// See http://howardhinnant.github.io/date_algorithms.html
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Current time, in seconds since the unix epoch
+/// Uses `SOURCE_DATE_EPOCH` instead if it's set, for reproducible builds
+pub(crate) fn now() -> i64 {
+    if let Some(time) = source_date_epoch() {
+        return time;
+    }
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Today's date, in UTC
+pub(crate) fn today() -> (i64, u32, u32) {
+    civil_from_days(now().div_euclid(86400))
+}
+
+/// Parse a `YYYY-MM-DD` date at the start of the string
+pub(crate) fn parse_ymd(text: &str) -> Option<(i64, u32, u32)> {
+    let bytes = text.as_bytes().get(0..10)?;
+
+    // check the shape
+    if !bytes.iter().enumerate().all(|(i, c)| {
+        if i == 4 || i == 7 {
+            *c == b'-'
+        } else {
+            c.is_ascii_digit()
+        }
+    }) {
+        return None;
+    }
+
+    let year = text[0..4].parse().ok()?;
+    let month = text[5..7].parse().ok()?;
+    let day = text[8..10].parse().ok()?;
+
+    // and the ranges
+    if (1..=12).contains(&month) && (1..=31).contains(&day) {
+        Some((year, month, day))
+    } else {
+        None
+    }
+}
+
+/// Days since the unix epoch from the year, month and day
+/// See http://howardhinnant.github.io/date_algorithms.html
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The build time from `SOURCE_DATE_EPOCH`, if set
+pub(crate) fn source_date_epoch() -> Option<i64> {
+    std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()
+}
+
+/// Parse a date and optional time, like `2024-01-31`, `2024-01-31 12:00`
+/// or `2024-01-31T12:00:00+01:00`, into seconds since the unix epoch
+pub(crate) fn parse_date(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (year, month, day) = parse_ymd(text)?;
+    if day > days_in_month(year, month) {
+        return None;
+    }
+    let date = days_from_civil(year, month, day) * 86400;
+
+    // only a date
+    let rest = &text[10..];
+    if rest.is_empty() {
+        return Some(date);
+    }
+
+    // time
+    let rest = rest.strip_prefix(['T', 't', ' '])?;
+    let number = |text: &str| -> Option<i64> {
+        text.bytes()
+            .all(|x| x.is_ascii_digit())
+            .then(|| text.parse().ok())
+            .flatten()
+    };
+    if rest.get(2..3)? != ":" {
+        return None;
+    }
+    let hour = number(rest.get(0..2)?)?;
+    let minute = number(rest.get(3..5)?)?;
+    let mut rest = &rest[5..];
+
+    // seconds, with optional fraction
+    let mut second = 0;
+    if let Some(seconds) = rest.strip_prefix(':') {
+        second = number(seconds.get(0..2)?)?;
+        rest = &seconds[2..];
+        if let Some(fraction) = rest.strip_prefix('.') {
+            let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+            if digits == 0 {
+                return None;
+            }
+            rest = &fraction[digits..];
+        }
+    }
+
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // timezone, utc if not given
+    let offset = match rest {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = rest[1..]
+                .split_once(':')
+                .unwrap_or((rest.get(1..3)?, &rest[3..]));
+            if hours.len() != 2 || minutes.len() != 2 {
+                return None;
+            }
+            sign * (number(hours)? * 3600 + number(minutes)? * 60)
+        }
+    };
+
+    Some(date + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Number of days in the month
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Names of months and days
+struct Locale {
+    months: [&'static str; 12],
+    short_months: [&'static str; 12],
+    days: [&'static str; 7],
+    short_days: [&'static str; 7],
+}
+
+/// Get a locale by name, like `en` or `nl-NL`
+fn locale(name: &str) -> Option<Locale> {
+    let language = name.split(['-', '_']).next().unwrap_or(name);
+    match language.to_ascii_lowercase().as_str() {
+        "en" => Some(Locale {
+            months: [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ],
+            short_months: [
+                "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+            ],
+            days: [
+                "Sunday",
+                "Monday",
+                "Tuesday",
+                "Wednesday",
+                "Thursday",
+                "Friday",
+                "Saturday",
+            ],
+            short_days: ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"],
+        }),
+        "nl" => Some(Locale {
+            months: [
+                "januari",
+                "februari",
+                "maart",
+                "april",
+                "mei",
+                "juni",
+                "juli",
+                "augustus",
+                "september",
+                "oktober",
+                "november",
+                "december",
+            ],
+            short_months: [
+                "jan", "feb", "mrt", "apr", "mei", "jun", "jul", "aug", "sep", "okt", "nov", "dec",
+            ],
+            days: [
+                "zondag",
+                "maandag",
+                "dinsdag",
+                "woensdag",
+                "donderdag",
+                "vrijdag",
+                "zaterdag",
+            ],
+            short_days: ["zo", "ma", "di", "wo", "do", "vr", "za"],
+        }),
+        "de" => Some(Locale {
+            months: [
+                "Januar",
+                "Februar",
+                "März",
+                "April",
+                "Mai",
+                "Juni",
+                "Juli",
+                "August",
+                "September",
+                "Oktober",
+                "November",
+                "Dezember",
+            ],
+            short_months: [
+                "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+            ],
+            days: [
+                "Sonntag",
+                "Montag",
+                "Dienstag",
+                "Mittwoch",
+                "Donnerstag",
+                "Freitag",
+                "Samstag",
+            ],
+            short_days: ["So", "Mo", "Di", "Mi", "Do", "Fr", "Sa"],
+        }),
+        "fr" => Some(Locale {
+            months: [
+                "janvier",
+                "février",
+                "mars",
+                "avril",
+                "mai",
+                "juin",
+                "juillet",
+                "août",
+                "septembre",
+                "octobre",
+                "novembre",
+                "décembre",
+            ],
+            short_months: [
+                "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.",
+                "nov.", "déc.",
+            ],
+            days: [
+                "dimanche", "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi",
+            ],
+            short_days: ["dim.", "lun.", "mar.", "mer.", "jeu.", "ven.", "sam."],
+        }),
+        "es" => Some(Locale {
+            months: [
+                "enero",
+                "febrero",
+                "marzo",
+                "abril",
+                "mayo",
+                "junio",
+                "julio",
+                "agosto",
+                "septiembre",
+                "octubre",
+                "noviembre",
+                "diciembre",
+            ],
+            short_months: [
+                "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sept", "oct", "nov", "dic",
+            ],
+            days: [
+                "domingo",
+                "lunes",
+                "martes",
+                "miércoles",
+                "jueves",
+                "viernes",
+                "sábado",
+            ],
+            short_days: ["dom", "lun", "mar", "mié", "jue", "vie", "sáb"],
+        }),
+        _ => None,
+    }
+}
+
+/// Format seconds since the unix epoch with a strftime-like pattern, in UTC
+pub(crate) fn format_date(time: i64, pattern: &str, locale_name: &str) -> Result<String, String> {
+    let locale = locale(locale_name).ok_or_else(|| format!("Unknown locale `{locale_name}`"))?;
+
+    let days = time.div_euclid(86400);
+    let seconds = time.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let weekday = (days + 4).rem_euclid(7) as usize;
+    let day_of_year = days - days_from_civil(year, 1, 1) + 1;
+
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('Y') => out.push_str(&year.to_string()),
+            Some('y') => out.push_str(&format!("{:02}", year.rem_euclid(100))),
+            Some('m') => out.push_str(&format!("{month:02}")),
+            Some('d') => out.push_str(&format!("{day:02}")),
+            Some('e') => out.push_str(&day.to_string()),
+            Some('j') => out.push_str(&format!("{day_of_year:03}")),
+            Some('H') => out.push_str(&format!("{hour:02}")),
+            Some('I') => out.push_str(&format!("{:02}", (hour + 11) % 12 + 1)),
+            Some('p') => out.push_str(if hour < 12 { "AM" } else { "PM" }),
+            Some('M') => out.push_str(&format!("{minute:02}")),
+            Some('S') => out.push_str(&format!("{second:02}")),
+            Some('B') => out.push_str(locale.months[month as usize - 1]),
+            Some('b') => out.push_str(locale.short_months[month as usize - 1]),
+            Some('A') => out.push_str(locale.days[weekday]),
+            Some('a') => out.push_str(locale.short_days[weekday]),
+            Some('u') => out.push_str(&(if weekday == 0 { 7 } else { weekday }).to_string()),
+            Some('F') => out.push_str(&format!("{year}-{month:02}-{day:02}")),
+            Some('T') => out.push_str(&format!("{hour:02}:{minute:02}:{second:02}")),
+            Some('s') => out.push_str(&time.to_string()),
+            Some('z') => out.push_str("+0000"),
+            Some('Z') => out.push_str("UTC"),
+            Some('%') => out.push('%'),
+            Some(c) => return Err(format!("Unknown format `%{c}` in `{pattern}`")),
+            None => return Err(format!("Expected a format after `%` in `{pattern}`")),
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+
+        // and back
+        for days in [-800_000, -1, 0, 59, 19782, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn ymd() {
+        assert_eq!(parse_ymd("2024-01-31-post.md"), Some((2024, 1, 31)));
+        assert_eq!(parse_ymd("2024-13-01"), None);
+        assert_eq!(parse_ymd("2024-1-31"), None);
+        assert_eq!(parse_ymd("post.md"), None);
+    }
+
+    #[test]
+    fn dates() {
+        let day = 19782 * 86400;
+        assert_eq!(parse_date("2024-02-29"), Some(day));
+        assert_eq!(parse_date(" 2024-02-29 12:34 "), Some(day + 45240));
+        assert_eq!(parse_date("2024-02-29T12:34:56.789Z"), Some(day + 45296));
+        assert_eq!(parse_date("2024-02-29T12:34:56+01:00"), Some(day + 41696));
+        assert_eq!(parse_date("2024-02-29t12:34:56-0130"), Some(day + 50696));
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-02-29 24:00"), None);
+        assert_eq!(parse_date("2024-02-29 12:34+1"), None);
+        assert_eq!(parse_date("2024-02-29 noon"), None);
+    }
+
+    #[test]
+    fn format() {
+        let time = 19782 * 86400 + 45296;
+        assert_eq!(
+            format_date(time, "%F %T %a %j %I%p %s", "en").unwrap(),
+            "2024-02-29 12:34:56 Thu 060 12PM 1709210096"
+        );
+        assert_eq!(
+            format_date(time, "%A %e %B %Y, 100%%", "nl-NL").unwrap(),
+            "donderdag 29 februari 2024, 100%"
+        );
+        assert_eq!(
+            format_date(-1, "%F %T", "fr").unwrap(),
+            "1969-12-31 23:59:59"
+        );
+        assert!(format_date(time, "%Q", "en").is_err());
+        assert!(format_date(time, "100%", "en").is_err());
+        assert!(format_date(time, "%F", "xx").is_err());
+    }
+}
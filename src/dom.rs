@@ -0,0 +1,735 @@
+use std::{
+    borrow::Cow,
+    cell::{Ref, RefCell},
+    io,
+    rc::Rc,
+};
+
+use html5ever::{
+    Attribute, LocalName, QualName,
+    interface::{ElementFlags, NodeOrText, QuirksMode, TreeSink},
+    local_name, ns, parse_document, parse_fragment,
+    serialize::{Serialize, SerializeOpts, Serializer, TraversalScope, serialize},
+    tendril::{StrTendril, TendrilSink},
+};
+use mlua::{ExternalResult, MetaMethod, Result, UserData, UserDataFields, UserDataMethods, Value};
+
+use crate::{data::create_array, selector::Selector};
+
+/// What a node is
+enum Data {
+    Document,
+    Doctype(String),
+    Element {
+        name: QualName,
+        attrs: Vec<Attribute>,
+        /// Contents, if this is a template
+        template: Option<usize>,
+    },
+    Text(String),
+    Comment(String),
+    ProcessingInstruction(String, String),
+}
+
+struct Node {
+    data: Data,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// Html document, with the document itself as the first node
+/// Removed nodes stay around, but are not reachable from the document
+pub(crate) struct Dom {
+    nodes: Vec<Node>,
+}
+
+impl Dom {
+    /// Parse a full document
+    pub(crate) fn parse(html: &str) -> Self {
+        parse_document(Sink::default(), Default::default())
+            .one(html)
+            .0
+            .into_inner()
+    }
+
+    /// Parse a fragment, as if it was the content of the given element
+    fn parse_fragment(html: &str, context: QualName) -> Self {
+        parse_fragment(Sink::default(), Default::default(), context, Vec::new())
+            .one(html)
+            .0
+            .into_inner()
+    }
+
+    fn push(&mut self, data: Data) -> usize {
+        self.nodes.push(Node {
+            data,
+            parent: None,
+            children: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Remove the node from it's parent
+    fn detach(&mut self, id: usize) {
+        if let Some(parent) = self.nodes[id].parent.take() {
+            self.nodes[parent].children.retain(|x| *x != id);
+        }
+    }
+
+    /// Add a node at the given position in the parent
+    fn insert(&mut self, parent: usize, index: usize, id: usize) {
+        self.detach(id);
+        self.nodes[id].parent = Some(parent);
+        self.nodes[parent].children.insert(index, id);
+    }
+
+    /// Add text at the given position in the parent, merged with the text before it if any
+    fn insert_text(&mut self, parent: usize, index: usize, text: &str) {
+        if let Some(previous) = index.checked_sub(1).map(|x| self.nodes[parent].children[x])
+            && let Data::Text(previous) = &mut self.nodes[previous].data
+        {
+            previous.push_str(text);
+        } else {
+            let id = self.push(Data::Text(text.to_string()));
+            self.insert(parent, index, id);
+        }
+    }
+
+    /// Copy a node from another dom into this one, and return the new node
+    fn import(&mut self, other: &Dom, id: usize) -> usize {
+        let data = match &other.nodes[id].data {
+            Data::Document => Data::Document,
+            Data::Doctype(name) => Data::Doctype(name.clone()),
+            Data::Element { name, attrs, .. } => Data::Element {
+                name: name.clone(),
+                attrs: attrs.clone(),
+                template: None,
+            },
+            Data::Text(text) => Data::Text(text.clone()),
+            Data::Comment(text) => Data::Comment(text.clone()),
+            Data::ProcessingInstruction(target, data) => {
+                Data::ProcessingInstruction(target.clone(), data.clone())
+            }
+        };
+        let new = self.push(data);
+        for child in other.children(id) {
+            let child = self.import(other, child);
+            let index = self.nodes[new].children.len();
+            self.insert(new, index, child);
+        }
+        new
+    }
+
+    /// Children of a node, or the template contents if it's a template
+    fn children(&self, id: usize) -> Vec<usize> {
+        match self.nodes[id].data {
+            Data::Element {
+                template: Some(contents),
+                ..
+            } => self.nodes[contents].children.clone(),
+            _ => self.nodes[id].children.clone(),
+        }
+    }
+
+    /// Tag name of an element
+    pub(crate) fn tag(&self, id: usize) -> Option<&str> {
+        match &self.nodes[id].data {
+            Data::Element { name, .. } => Some(&name.local),
+            _ => None,
+        }
+    }
+
+    /// Value of an attribute on an element
+    pub(crate) fn attr(&self, id: usize, key: &str) -> Option<&str> {
+        match &self.nodes[id].data {
+            Data::Element { attrs, .. } => attrs
+                .iter()
+                .find(|x| &*x.name.local == key)
+                .map(|x| &*x.value),
+            _ => None,
+        }
+    }
+
+    /// Set or remove an attribute on an element
+    fn set_attr(&mut self, id: usize, key: &str, value: Option<&str>) {
+        if let Data::Element { attrs, .. } = &mut self.nodes[id].data {
+            let index = attrs.iter().position(|x| &*x.name.local == key);
+            match (index, value) {
+                (Some(index), Some(value)) => attrs[index].value = value.into(),
+                (Some(index), None) => {
+                    attrs.remove(index);
+                }
+                (None, Some(value)) => attrs.push(Attribute {
+                    name: QualName::new(None, ns!(), LocalName::from(key)),
+                    value: value.into(),
+                }),
+                (None, None) => (),
+            }
+        }
+    }
+
+    /// Parent, if it's an element
+    pub(crate) fn parent_element(&self, id: usize) -> Option<usize> {
+        self.nodes[id].parent.filter(|x| self.tag(*x).is_some())
+    }
+
+    /// Element siblings before or after this node
+    fn sibling_element(&self, id: usize, before: bool) -> Option<usize> {
+        let siblings = &self.nodes[self.nodes[id].parent?].children;
+        let index = siblings.iter().position(|x| *x == id)?;
+        if before {
+            siblings[..index]
+                .iter()
+                .rev()
+                .find(|x| self.tag(**x).is_some())
+                .copied()
+        } else {
+            siblings[index + 1..]
+                .iter()
+                .find(|x| self.tag(**x).is_some())
+                .copied()
+        }
+    }
+
+    pub(crate) fn previous_element(&self, id: usize) -> Option<usize> {
+        self.sibling_element(id, true)
+    }
+
+    pub(crate) fn next_element(&self, id: usize) -> Option<usize> {
+        self.sibling_element(id, false)
+    }
+
+    /// Whether there are no elements or text in the node
+    pub(crate) fn is_empty(&self, id: usize) -> bool {
+        self.children(id)
+            .iter()
+            .all(|x| match &self.nodes[*x].data {
+                Data::Element { .. } => false,
+                Data::Text(text) => text.is_empty(),
+                _ => true,
+            })
+    }
+
+    /// All elements below the node that match the selector, in document order
+    fn select(&self, id: usize, selector: &Selector, out: &mut Vec<usize>, first: bool) {
+        // template contents are not part of the document
+        for child in self.nodes[id].children.clone() {
+            if first && !out.is_empty() {
+                return;
+            }
+            if self.tag(child).is_some() && selector.matches(self, child) {
+                out.push(child);
+            }
+            self.select(child, selector, out, first);
+        }
+    }
+
+    /// Text content of a node
+    fn text(&self, id: usize, out: &mut String) {
+        match &self.nodes[id].data {
+            Data::Text(text) => out.push_str(text),
+            _ => {
+                for child in &self.nodes[id].children {
+                    self.text(*child, out);
+                }
+            }
+        }
+    }
+
+    /// Remove all children of a node
+    fn clear(&mut self, id: usize) {
+        let parent = match self.nodes[id].data {
+            Data::Element {
+                template: Some(contents),
+                ..
+            } => contents,
+            _ => id,
+        };
+        for child in self.nodes[parent].children.clone() {
+            self.detach(child);
+        }
+    }
+
+    /// Serialize a node to html, with or without the node itself
+    fn html(&self, id: usize, include_node: bool) -> Result<String> {
+        let traversal_scope = match &self.nodes[id].data {
+            _ if include_node => TraversalScope::IncludeNode,
+            Data::Element { name, .. } => TraversalScope::ChildrenOnly(Some(name.clone())),
+            _ => TraversalScope::ChildrenOnly(None),
+        };
+
+        let mut out = Vec::new();
+        serialize(
+            &mut out,
+            &SerializeNode(self, id),
+            SerializeOpts {
+                traversal_scope,
+                ..Default::default()
+            },
+        )
+        .into_lua_err()?;
+
+        String::from_utf8(out).into_lua_err()
+    }
+}
+
+/// Node to serialize
+struct SerializeNode<'a>(&'a Dom, usize);
+
+impl Serialize for SerializeNode<'_> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: &mut S,
+        traversal_scope: TraversalScope,
+    ) -> io::Result<()> {
+        let SerializeNode(dom, id) = *self;
+        match (&dom.nodes[id].data, traversal_scope) {
+            (Data::Element { name, attrs, .. }, TraversalScope::IncludeNode) => {
+                serializer
+                    .start_elem(name.clone(), attrs.iter().map(|x| (&x.name, &x.value[..])))?;
+                for child in dom.children(id) {
+                    SerializeNode(dom, child).serialize(serializer, TraversalScope::IncludeNode)?;
+                }
+                serializer.end_elem(name.clone())
+            }
+            (Data::Element { .. } | Data::Document, _) => {
+                for child in dom.children(id) {
+                    SerializeNode(dom, child).serialize(serializer, TraversalScope::IncludeNode)?;
+                }
+                Ok(())
+            }
+            (Data::Doctype(name), _) => serializer.write_doctype(name),
+            (Data::Text(text), _) => serializer.write_text(text),
+            (Data::Comment(text), _) => serializer.write_comment(text),
+            (Data::ProcessingInstruction(target, data), _) => {
+                serializer.write_processing_instruction(target, data)
+            }
+        }
+    }
+}
+
+/// Sink to build the dom
+struct Sink(RefCell<Dom>);
+
+impl Default for Sink {
+    fn default() -> Self {
+        Self(RefCell::new(Dom {
+            nodes: vec![Node {
+                data: Data::Document,
+                parent: None,
+                children: Vec::new(),
+            }],
+        }))
+    }
+}
+
+impl TreeSink for Sink {
+    type Handle = usize;
+    type Output = Self;
+    type ElemName<'b> = Ref<'b, QualName>;
+
+    fn finish(self) -> Self::Output {
+        self
+    }
+
+    fn get_document(&self) -> usize {
+        0
+    }
+
+    fn elem_name<'b>(&'b self, target: &'b usize) -> Self::ElemName<'b> {
+        Ref::map(self.0.borrow(), |x| match &x.nodes[*target].data {
+            Data::Element { name, .. } => name,
+            _ => panic!("Not an element"),
+        })
+    }
+
+    fn create_element(&self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags) -> usize {
+        let mut dom = self.0.borrow_mut();
+        let template = flags.template.then(|| dom.push(Data::Document));
+        dom.push(Data::Element {
+            name,
+            attrs,
+            template,
+        })
+    }
+
+    fn create_comment(&self, text: StrTendril) -> usize {
+        self.0.borrow_mut().push(Data::Comment(text.to_string()))
+    }
+
+    fn create_pi(&self, target: StrTendril, data: StrTendril) -> usize {
+        self.0.borrow_mut().push(Data::ProcessingInstruction(
+            target.to_string(),
+            data.to_string(),
+        ))
+    }
+
+    fn append(&self, parent: &usize, child: NodeOrText<usize>) {
+        let mut dom = self.0.borrow_mut();
+        let index = dom.nodes[*parent].children.len();
+        match child {
+            NodeOrText::AppendNode(node) => dom.insert(*parent, index, node),
+            NodeOrText::AppendText(text) => dom.insert_text(*parent, index, &text),
+        }
+    }
+
+    fn append_based_on_parent_node(
+        &self,
+        element: &usize,
+        prev_element: &usize,
+        child: NodeOrText<usize>,
+    ) {
+        if self.0.borrow().nodes[*element].parent.is_some() {
+            self.append_before_sibling(element, child);
+        } else {
+            self.append(prev_element, child);
+        }
+    }
+
+    fn append_before_sibling(&self, sibling: &usize, child: NodeOrText<usize>) {
+        let mut dom = self.0.borrow_mut();
+        let Some(parent) = dom.nodes[*sibling].parent else {
+            return;
+        };
+        let index = dom.nodes[parent]
+            .children
+            .iter()
+            .position(|x| x == sibling)
+            .expect("Sibling is in it's parent");
+        match child {
+            NodeOrText::AppendNode(node) => dom.insert(parent, index, node),
+            NodeOrText::AppendText(text) => dom.insert_text(parent, index, &text),
+        }
+    }
+
+    fn append_doctype_to_document(&self, name: StrTendril, _: StrTendril, _: StrTendril) {
+        let mut dom = self.0.borrow_mut();
+        let doctype = dom.push(Data::Doctype(name.to_string()));
+        let index = dom.nodes[0].children.len();
+        dom.insert(0, index, doctype);
+    }
+
+    fn get_template_contents(&self, target: &usize) -> usize {
+        match self.0.borrow().nodes[*target].data {
+            Data::Element {
+                template: Some(contents),
+                ..
+            } => contents,
+            _ => panic!("Not a template element"),
+        }
+    }
+
+    fn same_node(&self, x: &usize, y: &usize) -> bool {
+        x == y
+    }
+
+    fn add_attrs_if_missing(&self, target: &usize, new: Vec<Attribute>) {
+        if let Data::Element { attrs, .. } = &mut self.0.borrow_mut().nodes[*target].data {
+            for attr in new {
+                if !attrs.iter().any(|x| x.name == attr.name) {
+                    attrs.push(attr);
+                }
+            }
+        }
+    }
+
+    fn remove_from_parent(&self, target: &usize) {
+        self.0.borrow_mut().detach(*target);
+    }
+
+    fn reparent_children(&self, node: &usize, new_parent: &usize) {
+        let mut dom = self.0.borrow_mut();
+        for child in dom.nodes[*node].children.clone() {
+            let index = dom.nodes[*new_parent].children.len();
+            dom.insert(*new_parent, index, child);
+        }
+    }
+
+    // we don't care what happens here
+    fn parse_error(&self, _: Cow<'static, str>) {}
+    fn set_quirks_mode(&self, _: QuirksMode) {}
+}
+
+/// Node in a html document, usable from lua
+#[derive(Clone)]
+pub(crate) struct HtmlNode {
+    dom: Rc<RefCell<Dom>>,
+    id: usize,
+}
+
+impl HtmlNode {
+    /// Parse a html document
+    pub(crate) fn parse(html: &str) -> Self {
+        Self {
+            dom: Rc::new(RefCell::new(Dom::parse(html))),
+            id: 0,
+        }
+    }
+
+    fn node(&self, id: usize) -> Self {
+        Self {
+            dom: self.dom.clone(),
+            id,
+        }
+    }
+
+    /// Elements below this node that match the selector
+    fn select(&self, selector: &str, first: bool) -> Result<Vec<Self>> {
+        let selector = Selector::parse(selector)?;
+        let mut out = Vec::new();
+        self.dom
+            .borrow()
+            .select(self.id, &selector, &mut out, first);
+        Ok(out.into_iter().map(|x| self.node(x)).collect())
+    }
+}
+
+impl UserData for HtmlNode {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        // tag name, nil if this is not an element
+        fields.add_field_method_get("tag", |_, this| {
+            Ok(this.dom.borrow().tag(this.id).map(str::to_string))
+        });
+
+        // parent node
+        fields.add_field_method_get("parent", |_, this| {
+            Ok(this.dom.borrow().nodes[this.id]
+                .parent
+                .map(|x| this.node(x)))
+        });
+
+        // child elements
+        fields.add_field_method_get("children", |lua, this| {
+            let dom = this.dom.borrow();
+            let children = dom
+                .children(this.id)
+                .into_iter()
+                .filter(|x| dom.tag(*x).is_some())
+                .map(|x| lua.create_userdata(this.node(x)).map(Value::UserData))
+                .collect::<Result<Vec<_>>>()?;
+            create_array(lua, children)
+        });
+
+        // text content
+        fields.add_field_method_get("text", |_, this| {
+            let mut out = String::new();
+            this.dom.borrow().text(this.id, &mut out);
+            Ok(out)
+        });
+        fields.add_field_method_set("text", |_, this, text: String| {
+            let mut dom = this.dom.borrow_mut();
+            dom.clear(this.id);
+            if !text.is_empty() {
+                let parent = match dom.nodes[this.id].data {
+                    Data::Element {
+                        template: Some(contents),
+                        ..
+                    } => contents,
+                    _ => this.id,
+                };
+                dom.insert_text(parent, 0, &text);
+            }
+            Ok(())
+        });
+
+        // html of the content
+        fields.add_field_method_get("html", |_, this| this.dom.borrow().html(this.id, false));
+        fields.add_field_method_set("html", |_, this, html: String| {
+            let mut dom = this.dom.borrow_mut();
+            let context = match &dom.nodes[this.id].data {
+                Data::Element { name, .. } => name.clone(),
+                _ => QualName::new(None, ns!(html), local_name!("body")),
+            };
+
+            // parse, the content ends up in a html element
+            let fragment = Dom::parse_fragment(&html, context);
+            dom.clear(this.id);
+            for root in fragment.children(0) {
+                for child in fragment.children(root) {
+                    let child = dom.import(&fragment, child);
+                    let parent = match dom.nodes[this.id].data {
+                        Data::Element {
+                            template: Some(contents),
+                            ..
+                        } => contents,
+                        _ => this.id,
+                    };
+                    let index = dom.nodes[parent].children.len();
+                    dom.insert(parent, index, child);
+                }
+            }
+            Ok(())
+        });
+
+        // html of the node itself and it's content
+        fields.add_field_method_get("outerhtml", |_, this| this.dom.borrow().html(this.id, true));
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // first element matching the selector
+        methods.add_method("query", |_, this, selector: String| {
+            Ok(this.select(&selector, true)?.into_iter().next())
+        });
+
+        // all elements matching the selector
+        methods.add_method("queryall", |lua, this, selector: String| {
+            let nodes = this
+                .select(&selector, false)?
+                .into_iter()
+                .map(|x| lua.create_userdata(x).map(Value::UserData))
+                .collect::<Result<Vec<_>>>()?;
+            create_array(lua, nodes)
+        });
+
+        // attributes
+        methods.add_method("getattr", |_, this, key: String| {
+            Ok(this.dom.borrow().attr(this.id, &key).map(str::to_string))
+        });
+        methods.add_method(
+            "setattr",
+            |_, this, (key, value): (String, Option<String>)| {
+                this.dom
+                    .borrow_mut()
+                    .set_attr(this.id, &key, value.as_deref());
+                Ok(())
+            },
+        );
+        methods.add_method("attrs", |lua, this, ()| {
+            let table = lua.create_table()?;
+            if let Data::Element { attrs, .. } = &this.dom.borrow().nodes[this.id].data {
+                for attr in attrs {
+                    table.raw_set(&*attr.name.local, &*attr.value)?;
+                }
+            }
+            Ok(table)
+        });
+
+        // remove from the document
+        methods.add_method("remove", |_, this, ()| {
+            this.dom.borrow_mut().detach(this.id);
+            Ok(())
+        });
+
+        // the document as html, or the node itself
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            this.dom.borrow().html(this.id, this.id != 0)
+        });
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: mlua::UserDataRef<Self>| {
+            Ok(Rc::ptr_eq(&this.dom, &other.dom) && this.id == other.id)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = concat!(
+        "<!DOCTYPE html><html><head></head><body>",
+        r#"<div id="main" class="box wide"><p lang="en-US">one</p><p data-x="a b">two</p>"#,
+        r#"<span></span><p><a href="https://example.com/x.pdf">three</a></p></div>"#,
+        "</body></html>"
+    );
+
+    /// Text of the elements that match, or the tag if there is no text
+    fn select(dom: &Dom, selector: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        dom.select(0, &Selector::parse(selector).unwrap(), &mut out, false);
+        out.into_iter()
+            .map(|x| {
+                let mut text = String::new();
+                dom.text(x, &mut text);
+                if text.is_empty() {
+                    dom.tag(x).unwrap().to_string()
+                } else {
+                    text
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn combinators() {
+        let dom = Dom::parse(HTML);
+        assert_eq!(select(&dom, "div > p"), ["one", "two", "three"]);
+        assert_eq!(select(&dom, "body p"), ["one", "two", "three"]);
+        assert_eq!(select(&dom, "html > p"), Vec::<String>::new());
+        assert_eq!(select(&dom, "p + span"), ["span"]);
+        assert_eq!(select(&dom, "p ~ p"), ["two", "three"]);
+        assert_eq!(select(&dom, "#main a"), ["three"]);
+        assert_eq!(select(&dom, "a, span"), ["span", "three"]);
+    }
+
+    #[test]
+    fn attributes() {
+        let dom = Dom::parse(HTML);
+        assert_eq!(select(&dom, "div.box.wide"), ["onetwothree"]);
+        assert_eq!(select(&dom, "div.narrow"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[lang]"), ["one"]);
+        assert_eq!(select(&dom, "[lang=en]"), Vec::<String>::new());
+        assert_eq!(select(&dom, "[lang|=en]"), ["one"]);
+        assert_eq!(select(&dom, "[data-x~=b]"), ["two"]);
+        assert_eq!(select(&dom, "[href^=https]"), ["three"]);
+        assert_eq!(select(&dom, "[href$='.pdf']"), ["three"]);
+        assert_eq!(select(&dom, "[href*=\"example\"]"), ["three"]);
+    }
+
+    #[test]
+    fn pseudo_classes() {
+        let dom = Dom::parse(HTML);
+        assert_eq!(select(&dom, "p:first-child"), ["one"]);
+        assert_eq!(select(&dom, "p:nth-child(2)"), ["two"]);
+        assert_eq!(select(&dom, "div :last-child"), ["three", "three"]);
+        assert_eq!(select(&dom, "a:only-child"), ["three"]);
+        assert_eq!(select(&dom, ":empty"), ["head", "span"]);
+        assert_eq!(select(&dom, "p:not([lang])"), ["two", "three"]);
+        assert_eq!(select(&dom, "p:not(:first-child, [data-x])"), ["three"]);
+    }
+
+    #[test]
+    fn edit_attributes() {
+        let mut dom = Dom::parse(HTML);
+        let mut out = Vec::new();
+        dom.select(0, &Selector::parse("div").unwrap(), &mut out, true);
+        let div = out[0];
+
+        dom.set_attr(div, "class", Some("box"));
+        dom.set_attr(div, "title", Some("a \"quote\""));
+        dom.set_attr(div, "id", None);
+        assert_eq!(dom.attr(div, "class"), Some("box"));
+        assert_eq!(dom.attr(div, "id"), None);
+        assert_eq!(select(&dom, ".wide"), Vec::<String>::new());
+        assert!(
+            dom.html(0, false)
+                .unwrap()
+                .contains(r#"<div class="box" title="a &quot;quote&quot;">"#)
+        );
+    }
+
+    #[test]
+    fn serialize() {
+        let dom = Dom::parse(HTML);
+        assert_eq!(dom.html(0, false).unwrap(), HTML);
+
+        let mut out = Vec::new();
+        dom.select(0, &Selector::parse("p").unwrap(), &mut out, true);
+        assert_eq!(
+            dom.html(out[0], true).unwrap(),
+            r#"<p lang="en-US">one</p>"#
+        );
+        assert_eq!(dom.html(out[0], false).unwrap(), "one");
+
+        // text is escaped, and parsing again gives the same html
+        let mut dom = Dom::parse("<p>a</p>");
+        let mut out = Vec::new();
+        dom.select(0, &Selector::parse("p").unwrap(), &mut out, true);
+        dom.clear(out[0]);
+        dom.insert_text(out[0], 0, "a < b & c");
+        let html = dom.html(0, false).unwrap();
+        assert_eq!(
+            html,
+            "<html><head></head><body><p>a &lt; b &amp; c</p></body></html>"
+        );
+        assert_eq!(Dom::parse(&html).html(0, false).unwrap(), html);
+    }
+}
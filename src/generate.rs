@@ -3,7 +3,9 @@ use std::{
     collections::{BTreeMap, BTreeSet, VecDeque},
     fs,
     io::Cursor,
+    path::Path,
     rc::Rc,
+    time::{Instant, UNIX_EPOCH},
 };
 
 use codemap::SpanLoc;
@@ -11,21 +13,40 @@ use glob::Pattern;
 use grass::{Logger, Options};
 use image::{ImageFormat, ImageReader};
 use latex2mathml::{DisplayStyle, latex_to_mathml};
-use mlua::{ErrorContext, ExternalResult, Lua, ObjectLike, Result, Table, Value, chunk};
+use mlua::{ErrorContext, ExternalResult, Function, Lua, ObjectLike, Result, Table, Value, chunk};
 use relative_path::{RelativePath, RelativePathBuf};
 
 use crate::{
+    asset::{fingerprint, placeholder, referenced, replace_placeholders},
+    bib::Bibliography,
+    config::{Config, Flags},
+    csv::parse_csv,
+    data::create_array,
+    date::{format_date, parse_date, source_date_epoch},
+    dom::HtmlNode,
     font::{chars_from_html, subset_font},
     highlight::Highlighter,
     html::text_from_html,
-    markdown::markdown,
-    path::{DoubleFileExt, HtmlToIndex},
+    image_info::image_info,
+    json::{parse_json, to_json},
+    markdown::{
+        extension, heading_ids, markdown, options, render_markdown, set_fence, set_render_hook,
+    },
+    math::Macros,
+    path::{DoubleFileExt, Draft, HtmlToIndex},
     print::print_warning,
-    templates::template,
+    report::{Pipeline, Report},
+    sandbox::{new_lua, site_file},
+    templates::{compile_mode, template},
+    toml::{parse_toml, to_toml},
+    wiki::Wiki,
+    xml::{parse_xml, to_xml},
+    yaml::parse_yaml,
 };
 
-#[derive(Debug)]
-struct SassLogger();
+/// Logger for sass, also keeps the warnings for the report
+#[derive(Debug, Default)]
+struct SassLogger(RefCell<Vec<String>>);
 
 impl Logger for SassLogger {
     fn debug(&self, location: SpanLoc, message: &str) {
@@ -40,15 +61,14 @@ impl Logger for SassLogger {
     }
 
     fn warn(&self, location: SpanLoc, message: &str) {
-        print_warning(
-            &format!(
-                "While parsing `{}:{}:{}`",
-                location.file.name(),
-                location.begin.line + 1,
-                location.begin.column + 1
-            ),
-            &message,
+        let context = format!(
+            "While parsing `{}:{}:{}`",
+            location.file.name(),
+            location.begin.line + 1,
+            location.begin.column + 1
         );
+        print_warning(&context, &message);
+        self.0.borrow_mut().push(format!("{context}: {message}"));
     }
 }
 
@@ -58,6 +78,69 @@ pub(crate) struct Site {
 
     /// What file to use for 404
     pub not_found: Option<Vec<u8>>,
+
+    /// How the site was built
+    pub report: Report,
+}
+
+impl Site {
+    /// Write the site to the output directory
+    /// If the previous version of the site is given, only write what changed
+    pub(crate) fn write(
+        &self,
+        output_path: &Path,
+        previous: Option<&BTreeMap<RelativePathBuf, Vec<u8>>>,
+    ) -> Result<()> {
+        // remove files that no longer exist
+        for file_path in previous
+            .iter()
+            .flat_map(|x| x.keys())
+            .filter(|x| !self.files.contains_key(*x))
+        {
+            fs::remove_file(file_path.to_path(output_path))
+                .into_lua_err()
+                .with_context(|_| {
+                    format!(
+                        "Failed to remove file `{}`",
+                        file_path.to_path(output_path).to_string_lossy()
+                    )
+                })?;
+        }
+
+        for (file_path, contents) in self.files.iter() {
+            // skip if it did not change
+            if previous.and_then(|x| x.get(file_path)) == Some(contents) {
+                continue;
+            }
+
+            // create the directory for it
+            fs::create_dir_all(file_path.to_path(output_path).parent().ok_or(
+                mlua::Error::external(format!(
+                    "output path `{}` could not be created",
+                    file_path.to_path(output_path).to_string_lossy()
+                )),
+            )?)
+            .into_lua_err()
+            .with_context(|_| {
+                format!(
+                    "output path `{}` could not be created",
+                    file_path.to_path(output_path).to_string_lossy()
+                )
+            })?;
+
+            // write the file
+            fs::write(file_path.to_path(output_path), contents)
+                .into_lua_err()
+                .with_context(|_| {
+                    format!(
+                        "Failed to write file `{}`",
+                        file_path.to_path(output_path).to_string_lossy()
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
 }
 
 const INDEX_FILES: &[&str] = &[
@@ -88,6 +171,118 @@ fn escape_html(html: &str) -> String {
     out
 }
 
+/// Render a table of contents as a nested list
+fn toc_html(toc: &Table, out: &mut String) -> Result<()> {
+    out.push_str("<ul>");
+    for entry in toc.sequence_values::<Table>() {
+        let entry = entry?;
+        out.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape_html(&entry.get::<String>("id")?),
+            escape_html(&entry.get::<String>("text")?)
+        ));
+
+        // nested headings
+        if let Some(children) = entry.get::<Option<Table>>("children")?
+            && children.raw_len() > 0
+        {
+            toc_html(&children, out)?;
+        }
+        out.push_str("</li>");
+    }
+    out.push_str("</ul>");
+    Ok(())
+}
+
+/// Files of all modules loaded with `require`
+fn required_modules(lua: &Lua) -> Result<BTreeSet<RelativePathBuf>> {
+    let paths: Vec<String> = lua
+        .load(chunk! {
+            local fennel = require("fennel")
+            local paths = {}
+            for name in pairs(package.loaded) do
+                local path = package.searchpath(name, package.path) or package.searchpath(name, fennel.path)
+                if path then table.insert(paths, path) end
+            end
+            for name in pairs(fennel["macro-loaded"]) do
+                local path = package.searchpath(name, fennel["macro-path"])
+                if path then table.insert(paths, path) end
+            end
+            return paths
+        })
+        .eval()?;
+
+    Ok(paths
+        .into_iter()
+        .map(|x| RelativePathBuf::from(x.replace('\\', "/").trim_start_matches("./")))
+        .collect())
+}
+
+/// Whether the pretty option is set
+fn pretty(options: &Option<Table>) -> Result<bool> {
+    Ok(options
+        .as_ref()
+        .map(|x| x.get::<Option<bool>>("pretty"))
+        .transpose()?
+        .flatten()
+        .unwrap_or(false))
+}
+
+/// Read a text file, for parsing structured data
+fn read_text(path: &RelativePath) -> Result<String> {
+    fs::read_to_string(path.to_path("."))
+        .into_lua_err()
+        .with_context(|_| format!("Could not read file `{path}`"))
+}
+
+/// Path of every page when paginating, the first page can have its own path
+fn page_paths(
+    pattern: &str,
+    first: Option<&str>,
+    total: usize,
+    safe: bool,
+) -> Result<Vec<RelativePathBuf>> {
+    (1..=total)
+        .map(|n| match first {
+            Some(first) if n == 1 => site_file(first, safe),
+            _ => site_file(&pattern.replace("{n}", &n.to_string()), safe),
+        })
+        .collect()
+}
+
+/// Url to link to a page, without the `index.html`
+fn page_url(path: &RelativePath) -> String {
+    let path = path.as_str();
+    format!("/{}", path.strip_suffix("index.html").unwrap_or(path))
+}
+
+/// Whether the output is text, which can contain asset urls
+fn is_text(path: &RelativePath) -> bool {
+    matches!(
+        path.extension(),
+        Some("html" | "htm" | "css" | "js" | "xml" | "json" | "txt")
+    )
+}
+
+/// Output of an asset, the file itself if it was generated, or else what was made from it
+fn asset_target(
+    path: &RelativePath,
+    files: &BTreeMap<RelativePathBuf, Vec<u8>>,
+    report: &Report,
+) -> Option<RelativePathBuf> {
+    if files.contains_key(path) {
+        Some(path.to_relative_path_buf())
+    } else {
+        report
+            .outputs
+            .iter()
+            .find(|(target, (source, _))| {
+                source.as_deref() == Some(path) && files.contains_key(*target)
+            })
+            .map(|(target, _)| target.clone())
+    }
+}
+
 thread_local! {
     /// Previous charset
     static CHARSET: RefCell<BTreeSet<char>> = RefCell::new(BTreeSet::new());
@@ -104,16 +299,75 @@ thread_local! {
 
 /// Generate the site
 /// Assumes that the current directory contains the site.conf file
-pub(crate) fn generate(dev: bool) -> Result<Site> {
+/// The output directory is skipped if it's inside the site
+pub(crate) fn generate(dev: bool, flags: &Flags, output: Option<&Path>) -> Result<Site> {
+    // what happened while building
+    let mut report = Report::default();
+    let start = Instant::now();
+
+    // read the config file
+    let config = Config::load(Path::new("site.conf"))?;
+
+    // where we write to, relative to the site
+    let output = output.and_then(|output| {
+        let root = std::env::current_dir()
+            .and_then(|x| x.canonicalize())
+            .ok()?;
+        let relative = output.strip_prefix(root).ok()?;
+        RelativePathBuf::from_path(relative).ok()
+    });
+
     // set up lua
-    let lua = unsafe { Lua::unsafe_new() };
+    let lua = new_lua(flags.safe)?;
+    let safe = flags.safe;
+
+    // whether files starting with a date are scheduled
+    let scheduled = config.scheduled;
 
     // load standard library
     let globals = lua.globals();
     globals.set("development", dev)?; // true if we are serving
+    globals.set("profile", flags.profile.as_deref())?; // profile we are building with
+    globals.set("null", Value::NULL)?; // null in arrays from data files
+
+    // config variables, read only
+    let variables = lua.create_table_from(config.variables(flags)?)?;
+    let metatable = lua.create_table()?;
+    metatable.set("__index", &variables)?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(|_, (_, key): (Value, Value)| -> Result<()> {
+            Err(mlua::Error::external(format!(
+                "Can't set `config.{}`, config is read only",
+                key.to_string()?
+            )))
+        })?,
+    )?;
+
+    // the proxy is empty, so iterate over the variables instead
+    let next = globals.get::<Function>("next")?;
+    metatable.set(
+        "__pairs",
+        lua.create_function(move |_, _: Value| Ok((next.clone(), variables.clone(), Value::Nil)))?,
+    )?;
+
+    // luajit only uses __pairs when built for lua 5.2, so pairs checks for it itself
+    lua.load(chunk! {
+        local pairs, getmetatable = pairs, getmetatable
+        _G.pairs = function(t)
+            local meta = getmetatable(t)
+            if type(meta) == "table" and meta.__pairs then return meta.__pairs(t) end
+            return pairs(t)
+        end
+    })
+    .exec()?;
+
+    let proxy = lua.create_table()?;
+    proxy.set_metatable(Some(metatable));
+    globals.set("config", proxy)?;
 
     // ignore a file
-    let ignore = Rc::new(RefCell::new(Vec::new()));
+    let ignore = Rc::new(RefCell::new(config.ignore.clone()));
     let ignore_clone = ignore.clone();
     globals.set(
         "ignorefiles",
@@ -128,7 +382,7 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     )?;
 
     // file to use as not found
-    let not_found = Rc::new(RefCell::new(None));
+    let not_found = Rc::new(RefCell::new(config.not_found.clone()));
     let not_found_clone = not_found.clone();
     globals.set(
         "notfound",
@@ -138,10 +392,26 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         })?,
     )?;
 
+    // tex macros for math
+    let macros = match &config.math_macros {
+        Some(path) => Macros::parse(
+            &fs::read_to_string(site_file(path.as_str(), safe)?.to_path("."))
+                .into_lua_err()
+                .with_context(|_| format!("Failed to read math macros `{path}`"))?,
+            path.as_str(),
+        )?,
+        None => Macros::default(),
+    };
+    let macros = Rc::new(macros);
+
     // math
+    let macros_clone = macros.clone();
     globals.set(
         "mathml",
         lua.create_function(move |_, (mathml, inline): (String, Option<bool>)| {
+            let mathml = macros_clone
+                .expand(&mathml)
+                .context("Failed to compile math")?;
             latex_to_mathml(
                 &mathml,
                 if inline.unwrap_or(false) {
@@ -196,8 +466,8 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     // read a file
     globals.set(
         "readfile",
-        lua.create_function(|lua, path: String| {
-            let path = RelativePathBuf::from(path);
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
             let data = fs::read(path.to_path("."))
                 .into_lua_err()
                 .with_context(|_| format!("Could not read file `{path}`"))?;
@@ -207,6 +477,217 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         })?,
     )?;
 
+    // read structured data
+    globals.set(
+        "readjson",
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
+            parse_json(lua, &read_text(&path)?, path.as_str())
+        })?,
+    )?;
+    globals.set(
+        "readtoml",
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
+            parse_toml(lua, &read_text(&path)?, path.as_str())
+        })?,
+    )?;
+    globals.set(
+        "readyaml",
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
+            parse_yaml(lua, &read_text(&path)?, path.as_str())
+        })?,
+    )?;
+    globals.set(
+        "readcsv",
+        lua.create_function(move |lua, (path, options): (String, Option<Table>)| {
+            let path = site_file(&path, safe)?;
+            let separator = options
+                .as_ref()
+                .map(|x| x.get::<Option<String>>("separator"))
+                .transpose()?
+                .flatten()
+                .unwrap_or(",".to_string());
+            let header = options
+                .as_ref()
+                .map(|x| x.get::<Option<bool>>("header"))
+                .transpose()?
+                .flatten()
+                .unwrap_or(false);
+
+            // only a single ascii character can separate fields
+            let &[separator] = separator.as_bytes() else {
+                return Err(mlua::Error::external(format!(
+                    "Separator `{separator}` must be a single ascii character"
+                )));
+            };
+
+            parse_csv(lua, &read_text(&path)?, path.as_str(), separator, header)
+        })?,
+    )?;
+    globals.set(
+        "readxml",
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
+            parse_xml(lua, &read_text(&path)?, path.as_str())
+        })?,
+    )?;
+
+    // markdown extensions to use, for all files or those matching the glob
+    let extensions = Rc::new(RefCell::new(config.markdown.clone()));
+    let extensions_clone = extensions.clone();
+    globals.set(
+        "markdownextensions",
+        lua.create_function(move |_, (table, glob): (Table, Option<String>)| {
+            let glob = glob
+                .map(|x| {
+                    Pattern::new(&x)
+                        .into_lua_err()
+                        .with_context(|_| format!("Failed to make glob pattern `{x}`"))
+                })
+                .transpose()?;
+
+            // sorted, so they are applied in the same order every time
+            let mut changes = Vec::new();
+            for pair in table.pairs::<String, bool>() {
+                let (name, enabled) = pair?;
+                let extension = extension(&name).ok_or_else(|| {
+                    mlua::Error::external(format!("Unknown markdown extension `{name}`"))
+                })?;
+                changes.push((name, extension, enabled));
+            }
+            changes.sort_by(|a, b| a.0.cmp(&b.0));
+
+            extensions_clone.borrow_mut().extend(
+                changes
+                    .into_iter()
+                    .map(|(_, extension, enabled)| (glob.clone(), extension, enabled)),
+            );
+            Ok(())
+        })?,
+    )?;
+
+    // render markdown
+    let extensions_clone = extensions.clone();
+    let macros_clone = macros.clone();
+    globals.set(
+        "rendermarkdown",
+        lua.create_function(move |lua, (text, table): (String, Option<Table>)| {
+            let mut options = options(&extensions_clone.borrow(), None);
+            let mut highlight = true;
+            for pair in table.iter().flat_map(|x| x.pairs::<String, bool>()) {
+                let (name, enabled) = pair?;
+                if name == "highlight" {
+                    highlight = enabled;
+                } else {
+                    let extension = extension(&name).ok_or_else(|| {
+                        mlua::Error::external(format!("Unknown markdown extension `{name}`"))
+                    })?;
+                    options.set(extension, enabled);
+                }
+            }
+            render_markdown(lua, &text, options, highlight, &macros_clone)
+        })?,
+    )?;
+
+    // render markdown elements with a function
+    globals.set(
+        "renderhook",
+        lua.create_function(|lua, (kind, hook): (String, Option<Function>)| {
+            set_render_hook(lua, &kind, hook)
+        })?,
+    )?;
+
+    // bibliography to cite from in markdown
+    let bibliography = Rc::new(RefCell::new(None));
+    let bibliography_clone = bibliography.clone();
+    globals.set(
+        "bibliography",
+        lua.create_function(move |_, (path, options): (String, Option<Table>)| {
+            let path = site_file(&path, safe)?;
+            let style = options
+                .map(|x| x.get::<Option<String>>("style"))
+                .transpose()?
+                .flatten();
+            let parsed = Bibliography::parse(&read_text(&path)?, path.as_str(), style.as_deref())?;
+            *bibliography_clone.borrow_mut() = Some(Rc::new(parsed));
+            Ok(())
+        })?,
+    )?;
+
+    // excerpts of markdown files that were processed
+    let excerpts = Rc::new(RefCell::new(BTreeMap::new()));
+    let excerpts_clone = excerpts.clone();
+    globals.set(
+        "excerpt",
+        lua.create_function(move |_, path: String| {
+            let path = RelativePathBuf::from(path).normalize();
+            Ok(excerpts_clone.borrow().get(&path).cloned())
+        })?,
+    )?;
+
+    // render fenced code blocks of a language with a function
+    globals.set(
+        "registerfence",
+        lua.create_function(|lua, (lang, handler): (String, Option<Function>)| {
+            set_fence(lua, &lang, handler)
+        })?,
+    )?;
+
+    // render a table of contents
+    globals.set(
+        "rendertoc",
+        lua.create_function(|lua, toc: Option<Table>| {
+            // curtoc is false outside markdown files
+            let toc = match toc {
+                Some(toc) => toc,
+                None => match lua.globals().get::<Value>("curtoc")? {
+                    Value::Table(toc) => toc,
+                    _ => {
+                        return Err(mlua::Error::external(
+                            "No table of contents given, and not in a markdown file",
+                        ));
+                    }
+                },
+            };
+
+            // nothing to render
+            if toc.raw_len() == 0 {
+                return Ok(String::new());
+            }
+
+            let mut out = String::from("<nav>");
+            toc_html(&toc, &mut out)?;
+            out.push_str("</nav>");
+            Ok(out)
+        })?,
+    )?;
+
+    // parse html into a document
+    globals.set(
+        "parsehtml",
+        lua.create_function(|_, html: String| Ok(HtmlNode::parse(&html)))?,
+    )?;
+
+    // serialize structured data
+    globals.set(
+        "tojson",
+        lua.create_function(|lua, (value, options): (Value, Option<Table>)| {
+            to_json(lua, &value, pretty(&options)?)
+        })?,
+    )?;
+    globals.set(
+        "totoml",
+        lua.create_function(|lua, table: Table| to_toml(lua, &table))?,
+    )?;
+    globals.set(
+        "toxml",
+        lua.create_function(|lua, (value, options): (Value, Option<Table>)| {
+            to_xml(lua, &value, pretty(&options)?)
+        })?,
+    )?;
+
     // escape html
     globals.set(
         "escapehtml",
@@ -218,18 +699,126 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     let emit_extra_clone = emit_extra.clone();
     globals.set(
         "emitfile",
-        lua.create_function(move |_, (path, content): (String, mlua::String)| {
-            emit_extra_clone
-                .borrow_mut()
-                .insert(RelativePathBuf::from(path), content.as_bytes().to_owned());
+        lua.create_function(move |lua, (path, content): (String, mlua::String)| {
+            // file that emitted this, if any
+            let source = lua
+                .globals()
+                .get::<Value>("curfile")?
+                .as_str()
+                .map(|x| RelativePathBuf::from(x.as_ref()));
+
+            emit_extra_clone.borrow_mut().insert(
+                site_file(&path, safe)?,
+                (content.as_bytes().to_owned(), source),
+            );
             Ok(())
         })?,
     )?;
+
+    // split items over multiple pages, and emit them
+    let emit_extra_clone = emit_extra.clone();
+    globals.set(
+        "paginate",
+        lua.create_function(
+            move |lua, (items, options, render): (Table, Option<Table>, Function)| {
+                let perpage = options
+                    .as_ref()
+                    .map(|x| x.get::<Option<usize>>("perpage"))
+                    .transpose()?
+                    .flatten()
+                    .unwrap_or(10);
+                let pattern = options
+                    .as_ref()
+                    .map(|x| x.get::<Option<String>>("path"))
+                    .transpose()?
+                    .flatten()
+                    .ok_or_else(|| mlua::Error::external("Expected a `path` to paginate to"))?;
+                let first = options
+                    .as_ref()
+                    .map(|x| x.get::<Option<String>>("first"))
+                    .transpose()?
+                    .flatten();
+
+                if perpage == 0 {
+                    return Err(mlua::Error::external("`perpage` must be at least 1"));
+                }
+                if !pattern.contains("{n}") {
+                    return Err(mlua::Error::external(format!(
+                        "Expected path `{pattern}` to contain `{{n}}`"
+                    )));
+                }
+
+                // file that emitted this, if any
+                let source = lua
+                    .globals()
+                    .get::<Value>("curfile")?
+                    .as_str()
+                    .map(|x| RelativePathBuf::from(x.as_ref()));
+
+                // first page goes to the canonical url, which is the current file if any
+                let target = lua
+                    .globals()
+                    .get::<Value>("curtarget")?
+                    .as_str()
+                    .map(|x| x.to_string());
+                let first = first.or(target.clone());
+
+                // path of every page
+                let items = items
+                    .sequence_values::<Value>()
+                    .collect::<Result<Vec<_>>>()?;
+                let total = items.len().div_ceil(perpage).max(1);
+                let paths = page_paths(&pattern, first.as_deref(), total, safe)?;
+
+                // there's always one page, even without items
+                let pages = if items.is_empty() {
+                    vec![&items[..]]
+                } else {
+                    items.chunks(perpage).collect()
+                };
+
+                // render all pages
+                let mut first_page = None;
+                for (i, (chunk, path)) in pages.into_iter().zip(&paths).enumerate() {
+                    let page = lua.create_table()?;
+                    page.set("items", create_array(lua, chunk.to_vec())?)?;
+                    page.set("number", i + 1)?;
+                    page.set("total", total)?;
+                    page.set("path", path.as_str())?;
+                    page.set("url", page_url(path))?;
+                    page.set("first", page_url(&paths[0]))?;
+                    page.set("last", page_url(&paths[total - 1]))?;
+                    page.set("prev", i.checked_sub(1).map(|x| page_url(&paths[x])))?;
+                    page.set("next", paths.get(i + 1).map(|x| page_url(x)))?;
+
+                    let content = render
+                        .call::<mlua::String>(page)
+                        .with_context(|_| format!("Failed to render page `{path}`"))?;
+
+                    // the current file is the first page, so don't emit it
+                    if i == 0 {
+                        first_page = Some(content.clone());
+                        if target.as_deref() == Some(path.as_str()) {
+                            continue;
+                        }
+                    }
+
+                    emit_extra_clone.borrow_mut().insert(
+                        path.clone(),
+                        (content.as_bytes().to_owned(), source.clone()),
+                    );
+                }
+
+                Ok(first_page)
+            },
+        )?,
+    )?;
+
     // list files in directory
     globals.set(
         "listfiles",
-        lua.create_function(|lua, path: String| {
-            let path = RelativePathBuf::from(path);
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
             let res = lua.create_table()?;
             for entry in path
                 .to_path(".")
@@ -241,6 +830,15 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
                     .into_lua_err()
                     .with_context(|_| format!("Failed to list files in `{path}`"))?;
 
+                // skip drafts if we are not developing
+                if !dev
+                    && !path
+                        .join(entry.file_name().to_string_lossy().as_ref())
+                        .is_published(scheduled)
+                {
+                    continue;
+                }
+
                 // if it's a file, add
                 if entry
                     .file_type()
@@ -263,8 +861,8 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     // list directories in directory
     globals.set(
         "listdirs",
-        lua.create_function(|lua, path: String| {
-            let path = RelativePathBuf::from(path);
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
             let res = lua.create_table()?;
             for entry in path
                 .to_path(".")
@@ -276,6 +874,15 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
                     .into_lua_err()
                     .with_context(|_| format!("Failed to list directories in `{path}`"))?;
 
+                // skip drafts if we are not developing
+                if !dev
+                    && !path
+                        .join(entry.file_name().to_string_lossy().as_ref())
+                        .is_published(scheduled)
+                {
+                    continue;
+                }
+
                 // if it's a file, add
                 if entry
                     .file_type()
@@ -295,8 +902,93 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         })?,
     )?;
 
+    // whether a file is a draft, or scheduled to be published later
+    globals.set(
+        "isdraft",
+        lua.create_function(move |_, path: String| {
+            Ok(!RelativePathBuf::from(path).is_published(scheduled))
+        })?,
+    )?;
+
+    // parse a date to seconds since the unix epoch
+    globals.set(
+        "parsedate",
+        lua.create_function(|_, date: String| {
+            parse_date(&date)
+                .ok_or_else(|| mlua::Error::external(format!("Could not parse date `{date}`")))
+        })?,
+    )?;
+
+    // format a date
+    globals.set(
+        "formatdate",
+        lua.create_function(
+            |_, (date, pattern, locale): (Value, Option<String>, Option<String>)| {
+                let time = match date {
+                    Value::Integer(time) => time,
+                    Value::Number(time) => time as i64,
+                    Value::String(date) => parse_date(&date.to_str()?).ok_or_else(|| {
+                        mlua::Error::external(format!("Could not parse date `{}`", date.display()))
+                    })?,
+                    _ => return Err(mlua::Error::external("Expected a date string or number")),
+                };
+
+                format_date(
+                    time,
+                    pattern.as_deref().unwrap_or("%Y-%m-%d"),
+                    locale.as_deref().unwrap_or("en"),
+                )
+                .map_err(mlua::Error::external)
+            },
+        )?,
+    )?;
+
+    // size and modification time of a file
+    globals.set(
+        "filestat",
+        lua.create_function(move |lua, path: String| {
+            let path = site_file(&path, safe)?;
+            let metadata = fs::metadata(path.to_path("."))
+                .into_lua_err()
+                .with_context(|_| format!("Could not read file `{path}`"))?;
+
+            // files are never newer than the build time, if it's set
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                .map(|x| x.as_secs() as i64)
+                .map(|x| source_date_epoch().map(|y| x.min(y)).unwrap_or(x));
+
+            let table = lua.create_table()?;
+            table.set("size", metadata.len())?;
+            table.set("modified", modified)?;
+            table.set("isdir", metadata.is_dir())?;
+            Ok(table)
+        })?,
+    )?;
+
+    // asset with the content hash in the name, resolved once all files are generated
+    let assets = Rc::new(RefCell::new(Vec::new()));
+    let assets_clone = assets.clone();
+    globals.set(
+        "asset",
+        lua.create_function(move |_, path: String| {
+            let path = site_file(&path, safe)?.normalize();
+            let mut assets = assets_clone.borrow_mut();
+            let index = match assets.iter().position(|x| x == &path) {
+                Some(index) => index,
+                None => {
+                    assets.push(path);
+                    assets.len() - 1
+                }
+            };
+            Ok(placeholder(index))
+        })?,
+    )?;
+
     // add chars to subset
-    let subset_chars = Rc::new(RefCell::new(BTreeSet::new()));
+    let subset_chars = Rc::new(RefCell::new(BTreeSet::from_iter(config.extra.chars())));
     let subset_cloned = subset_chars.clone();
     globals.set(
         "extendsubset",
@@ -401,6 +1093,37 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         })?,
     )?;
 
+    // format and size of an image, from a file in the site or it's content
+    globals.set(
+        "imageinfo",
+        lua.create_function(move |lua, image: mlua::String| {
+            let bytes = image.as_bytes();
+
+            // file, if there is one at that path
+            // checked in the site first, so safe mode doesn't tell what exists outside it
+            let file = match str::from_utf8(&bytes).ok().filter(|x| !x.contains('\0')) {
+                Some(path) => Some(site_file(path, safe)?),
+                None => None,
+            };
+            let data = match file.filter(|x| x.to_path(".").is_file()) {
+                Some(path) => fs::read(path.to_path("."))
+                    .into_lua_err()
+                    .with_context(|_| format!("Could not read file `{path}`"))?,
+                None => bytes.to_vec(),
+            };
+
+            let (format, width, height) = image_info(&data)
+                .ok_or("Could not read image")
+                .into_lua_err()?;
+
+            let table = lua.create_table()?;
+            table.set("format", format)?;
+            table.set("width", width)?;
+            table.set("height", height)?;
+            Ok(table)
+        })?,
+    )?;
+
     // TODO: search index, see how zola does it
 
     // currently not working inside a file
@@ -408,6 +1131,8 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     lua.globals().set("curdir", false)?;
     lua.globals().set("curtarget", false)?;
     lua.globals().set("curtargetdir", false)?;
+    lua.globals().set("curtoc", false)?;
+    lua.globals().set("curexcerpt", false)?;
 
     // load syntaxes
     // cache them to reuse the regexes and avoid having to reload the lua file
@@ -425,6 +1150,7 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     } else {
         // else, load from cache
         *syntaxes.borrow_mut() = SYNTAXES.with_borrow(|x| x.clone());
+        report.cache_hit("syntaxes");
     };
 
     // load fennel
@@ -440,20 +1166,43 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     .exec()
     .context("failed to install fennel")?;
 
+    // require modules relative to the site first, for both lua and fennel
+    // the default paths can be outside the site, so they are dropped in safe mode
+    lua.load(chunk! {
+        local fennel = require("fennel")
+        local function paths(site, default)
+            if $safe then return site end
+            return site .. ";" .. default
+        end
+        package.path = paths("./?.lua;./?/init.lua", package.path)
+        fennel.path = paths("./?.fnl;./?/init.fnl", fennel.path)
+        fennel["macro-path"] = paths("./?.fnl;./?/init-macros.fnl;./?/init.fnl", fennel["macro-path"])
+        table.insert(package.loaders or package.searchers, fennel.searcher)
+    })
+    .exec()
+    .context("Failed to set up require")?;
+
     // setup script
-    let setup_path = if RelativePathBuf::from("site.lua").to_path(".").exists() {
-        RelativePathBuf::from("site.lua")
+    let setup_path = if let Some(setup) = &config.setup {
+        Some(site_file(setup.as_str(), safe)?)
+    } else if RelativePathBuf::from("site.lua").to_path(".").exists() {
+        Some(RelativePathBuf::from("site.lua"))
     } else if RelativePathBuf::from("site.fnl").to_path(".").exists() {
-        RelativePathBuf::from("site.fnl")
+        Some(RelativePathBuf::from("site.fnl"))
+    } else if RelativePathBuf::from("site.conf").to_path(".").exists() {
+        None
     } else {
-        return Err(mlua::Error::external("no `site.lua` or `site.fnl` found"));
+        return Err(mlua::Error::external(
+            "no `site.lua`, `site.fnl` or `site.conf` found",
+        ));
     };
-    let setup_module: Option<Table> = match setup_path.extension() {
-        Some("lua") => lua
+    let setup_module: Option<Table> = match setup_path.as_ref().map(|x| (x, x.extension())) {
+        None => None,
+        Some((setup_path, Some("lua"))) => lua
             .load(setup_path.to_path("."))
             .eval()
             .with_context(|_| format!("Failed to load include file `{setup_path}`"))?,
-        Some("fnl") => {
+        Some((setup_path, Some("fnl"))) => {
             let code = fs::read_to_string(setup_path.to_path("."))
                 .into_lua_err()
                 .with_context(|_| format!("Failed to load include file `{setup_path}`"))?;
@@ -463,7 +1212,7 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
                 )
                 .eval()?
         }
-        Some(_) | None => {
+        Some((setup_path, _)) => {
             return Err(mlua::Error::external(format!(
                 "File `{setup_path}` is not a lua or fennel file, and can't be run as setup script",
             )));
@@ -478,6 +1227,9 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         }
     }
 
+    report.stage("setup", start.elapsed());
+    let start = Instant::now();
+
     // files to process, in that order
     let mut process = Vec::new();
 
@@ -487,8 +1239,11 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         // skip if this path is in the skippable list, one of the main files, or is hidden
         // don't include output
         if path.starts_with(".dist")
-            // don't include site file
-            || path == "site.lua" || path == "site.fnl"
+            || config.output.as_ref().map(|x| path.starts_with(x)).unwrap_or(false)
+            || output.as_ref().map(|x| path.starts_with(x)).unwrap_or(false)
+            // don't include site files
+            || path == "site.lua" || path == "site.fnl" || path == "site.conf"
+            || Some(&path) == setup_path.as_ref()
             // don't include any to ignore
             || ignore.borrow().iter().any(|x| x.matches(path.as_str()))
             // don't include hidden files
@@ -496,6 +1251,8 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
                 .file_name()
                 .map(|x| x.starts_with('.'))
                 .unwrap_or(false)
+            // don't include drafts, unless we are developing
+            || !dev && !path.is_published(scheduled)
         {
             continue;
         // if it's a directory, read all directories
@@ -513,6 +1270,17 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
                     .into_lua_err()
                     .context("Failed to read directory entry")?;
 
+                // symlinks can lead outside the site, so skip them in safe mode
+                if safe
+                    && entry
+                        .file_type()
+                        .into_lua_err()
+                        .context("Failed to get file type")?
+                        .is_symlink()
+                {
+                    continue;
+                }
+
                 let file_path = path.join(entry.file_name().into_string().map_err(|x| {
                     mlua::Error::external(format!(
                         "Failed to convert path `{}` to a utf8 string",
@@ -562,6 +1330,43 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         }
     }
 
+    report.stage("traverse", start.elapsed());
+    let start = Instant::now();
+
+    // pages and their headings, if any markdown file has wiki links
+    let mut wiki = Wiki::default();
+    if process.iter().any(|x| {
+        x.extension() == Some("md")
+            && options(&extensions.borrow(), Some(x))
+                .contains(pulldown_cmark::Options::ENABLE_WIKILINKS)
+    }) {
+        for path in &process {
+            let templated = path.has_double_ext("lua") || path.has_double_ext("fnl");
+            if path.extension() == Some("md") {
+                let Some(target) = path.with_extension("html").without_double_ext() else {
+                    continue;
+                };
+                let target = target.without_draft();
+                let target = target.html_to_index().unwrap_or(target);
+                let content = fs::read_to_string(path.to_path("."))
+                    .into_lua_err()
+                    .with_context(|_| format!("Failed to read `{path}`"))?;
+                let headings = heading_ids(&content, options(&extensions.borrow(), Some(path)));
+                wiki.add(path, &target, Some(headings));
+            } else if let Some(target) = if templated {
+                path.without_double_ext()
+            } else {
+                Some(path.clone())
+            }
+            .filter(|x| x.extension() == Some("htm") || x.extension() == Some("html"))
+            {
+                let target = target.without_draft();
+                let target = target.html_to_index().unwrap_or(target);
+                wiki.add(path, &target, None);
+            }
+        }
+    }
+
     // final files
     let mut files = BTreeMap::new();
 
@@ -579,26 +1384,43 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         if path.extension().map(|x| x == "md").unwrap_or(false) {
             // parse
             let name = path.clone();
-            let (res, functions) = markdown(
+            let options = options(&extensions.borrow(), Some(&path));
+            let bibliography = bibliography.borrow().clone();
+            let (res, functions, toc, excerpt) = markdown(
                 &lua,
                 &fs::read_to_string(path.to_path("."))
                     .into_lua_err()
                     .with_context(|_| format!("Failed to read `{path}`"))?,
                 &name,
+                options,
+                &wiki,
+                bibliography.as_deref(),
+                &macros,
+                compile_mode(&config.compile, &name),
             )
             .with_context(|_| format!("Failed to template file `{path}`"))?;
+            excerpts.borrow_mut().insert(name.clone(), excerpt.clone());
 
             // make the final path
-            let path =
-                path.with_extension("html")
-                    .without_double_ext()
-                    .ok_or(mlua::Error::external(format!(
-                        "Expected path `{path}` to have a second `.lua` or `.fnl` extension"
-                    )))?;
+            let path = path
+                .with_extension("html")
+                .without_double_ext()
+                .ok_or(mlua::Error::external(format!(
+                    "Expected path `{path}` to have a second `.lua` or `.fnl` extension"
+                )))?
+                .without_draft();
             let path = path.html_to_index().unwrap_or(path);
 
             // template it
-            to_template.push_back((path, name, res, functions));
+            report.output(path.clone(), Some(name.clone()), Pipeline::Markdown);
+            to_template.push_back((
+                path,
+                name,
+                res,
+                functions,
+                Value::Table(toc),
+                Value::Table(excerpt),
+            ));
         }
         // .fnl or .lua second ext? template
         else if path.has_double_ext("fnl") || path.has_double_ext("lua") {
@@ -610,6 +1432,7 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
                     .into_lua_err()
                     .with_context(|_| format!("Failed to read `{path}`"))?,
                 &name,
+                compile_mode(&config.compile, &name),
             )
             .with_context(|_| format!("Failed to template file `{path}`"))?;
 
@@ -618,11 +1441,20 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
                 .without_double_ext()
                 .ok_or(mlua::Error::external(format!(
                     "Expected path `{path}` to have a second `.lua` or `.fnl` extension",
-                )))?;
+                )))?
+                .without_draft();
             let path = path.html_to_index().unwrap_or(path);
 
             // template it
-            to_template.push_back((path, name, res, functions));
+            report.output(path.clone(), Some(name.clone()), Pipeline::Template);
+            to_template.push_back((
+                path,
+                name,
+                res,
+                functions,
+                Value::Boolean(false),
+                Value::Boolean(false),
+            ));
         }
         // .subset second ext? subset
         else if path.has_double_ext("subset") {
@@ -645,9 +1477,11 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         // else? emit normally
         else {
             // make the final path
-            let final_path = path.html_to_index().unwrap_or(path.clone());
+            let final_path = path.without_draft();
+            let final_path = final_path.html_to_index().unwrap_or(final_path);
 
             // insert it into the files
+            report.output(final_path.clone(), Some(path.clone()), Pipeline::Copy);
             files.insert(
                 final_path,
                 fs::read(path.to_path("."))
@@ -657,8 +1491,11 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         }
     }
 
+    report.stage("process", start.elapsed());
+    let start = Instant::now();
+
     // apply templating
-    while let Some((path, name, mut res, mut functions)) = to_template.pop_front() {
+    while let Some((path, name, mut res, mut functions, toc, excerpt)) = to_template.pop_front() {
         // set environment
         lua.globals().set("curfile", name.as_str())?;
 
@@ -673,6 +1510,12 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         lua.globals()
             .set("curtargetdir", path.parent().map(RelativePath::as_str))?;
 
+        // table of contents, if this was markdown
+        lua.globals().set("curtoc", &toc)?;
+
+        // excerpt, if this was markdown
+        lua.globals().set("curexcerpt", &excerpt)?;
+
         // run
         if let Some(fun) = functions.pop_front() {
             if let Some(fun) = fun.as_function() {
@@ -690,24 +1533,66 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
             }
 
             // need to process again
-            to_template.push_back((path, name, res, functions));
+            to_template.push_back((path, name, res, functions, toc, excerpt));
         } else {
             files.insert(path, res.into_bytes());
         }
     }
 
+    report.stage("templating", start.elapsed());
+    let start = Instant::now();
+
     // add emitted files
-    files.extend(emit_extra.replace(Default::default()).into_iter());
+    for (path, (content, source)) in emit_extra.replace(Default::default()) {
+        report.output(path.clone(), source, Pipeline::EmitFile);
+        files.insert(path, content);
+    }
+
+    // mark drafts when developing
+    if dev {
+        for (path, (source, _)) in report.outputs.iter() {
+            if let Some(source) = source.as_ref().filter(|x| !x.is_published(scheduled))
+                && let Some(file) = files
+                    .get_mut(path)
+                    .filter(|_| path.extension() == Some("htm") || path.extension() == Some("html"))
+            {
+                let status = if source.is_draft() {
+                    "Draft, not included in the final build".to_string()
+                } else {
+                    let (year, month, day) = source.publish_date().unwrap_or_default();
+                    format!(
+                        "Scheduled, not included in the final build before {year:04}-{month:02}-{day:02}"
+                    )
+                };
+                // before the end of the body, if there is one
+                let marker = format!(include_str!("draft_marker.html"), status);
+                let end = file
+                    .windows(7)
+                    .rposition(|x| x.eq_ignore_ascii_case(b"</body>"))
+                    .unwrap_or(file.len());
+                file.splice(end..end, marker.into_bytes());
+            }
+        }
+    }
 
     // we got all files to ignore, filter
     files.retain(|k, _| !ignore.borrow().iter().any(|x| x.matches(k.as_str())));
 
+    // required modules are part of the build, so don't copy them
+    let modules = required_modules(&lua)?;
+    files.retain(|k, _| {
+        !report.outputs.get(k).is_some_and(|(source, pipeline)| {
+            matches!(pipeline, Pipeline::Copy)
+                && source.as_ref().is_some_and(|x| modules.contains(x))
+        })
+    });
+
     // do sass
     for path in to_sass
         .into_iter()
         .filter(|x| !ignore.borrow().iter().any(|y| y.matches(x.as_str())))
     {
-        let logger = SassLogger();
+        let logger = SassLogger::default();
         let opts = Options::default()
             .style(if dev {
                 grass::OutputStyle::Expanded
@@ -721,9 +1606,15 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
             .with_context(|_| format!("Failed to compile sass file `{path}`"))?;
 
         // export a css file
-        files.insert(path.with_extension("css"), res.into_bytes());
+        report.warnings.extend(logger.0.take());
+        let target = path.with_extension("css").without_draft();
+        report.output(target.clone(), Some(path.clone()), Pipeline::Sass);
+        files.insert(target, res.into_bytes());
     }
 
+    report.stage("sass", start.elapsed());
+    let start = Instant::now();
+
     // do font subsetting
     // find what characters we have
     let mut charset = BTreeSet::new();
@@ -731,6 +1622,14 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     // load from extra
     charset.extend(subset_chars.borrow().iter());
 
+    // asset urls need the subsetted fonts, as those can be assets too, so use their path
+    // for now, the hash only adds the hex digits
+    let stand_ins = assets
+        .borrow()
+        .iter()
+        .map(|x| format!("/{}", asset_target(x, &files, &report).unwrap_or(x.clone())))
+        .collect::<Vec<_>>();
+
     // load from files
     for (path, file) in files
         .iter()
@@ -738,6 +1637,15 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     {
         // only work on html files
         if path.extension() == Some("htm") || path.extension() == Some("html") {
+            // with asset urls instead of placeholders
+            let file = match replace_placeholders(file, &stand_ins) {
+                Some(replaced) => {
+                    charset.extend("0123456789abcdef.".chars());
+                    replaced
+                }
+                None => file.clone(),
+            };
+
             // interpret as utf8
             let string = str::from_utf8(&file)
                 .into_lua_err()
@@ -768,8 +1676,12 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
         let font = fs::read(path.to_path("."))
             .into_lua_err()
             .with_context(|_| format!("Failed to read file `{path}`"))?;
-        let subsetted = if let Some(subsetted) = SUBSETTED.with_borrow(|x| x.get(&font).cloned()) {
+        let subsetted = if !config.subset {
+            // not subsetting, use the font as is
+            font
+        } else if let Some(subsetted) = SUBSETTED.with_borrow(|x| x.get(&font).cloned()) {
             // get from cache
+            report.cache_hit("subset");
             subsetted
         } else {
             // else, subset and add
@@ -782,14 +1694,86 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
             // return the font we have
             subsetted
         };
-        let path = path
+        let target = path
             .without_double_ext()
             .ok_or(mlua::Error::external(format!(
                 "Expected path `{path}` to have a second `.subset` extension",
-            )))?;
-        files.insert(path, subsetted);
+            )))?
+            .without_draft();
+        report.output(target.clone(), Some(path.clone()), Pipeline::Subset);
+        files.insert(target, subsetted);
+    }
+
+    report.stage("subset", start.elapsed());
+    let start = Instant::now();
+
+    // fingerprint assets, from the output if it was generated, or else the file itself
+    // the original stays at it's own path too, for anything that links to it directly
+    let mut pending = Vec::new();
+    for path in assets.take() {
+        let (target, content) = match asset_target(&path, &files, &report) {
+            Some(target) => {
+                let content = files[&target].clone();
+                (target, content)
+            }
+            None => {
+                let content = fs::read(path.to_path("."))
+                    .into_lua_err()
+                    .with_context(|_| format!("Could not find asset `{path}`"))?;
+                (path.clone(), content)
+            }
+        };
+
+        pending.push((pending.len(), path, target, content));
     }
 
+    // assets can refer to other assets, so those need their url, and hash, first
+    // only text can refer to them, as placeholders could be part of binary files by chance
+    let mut urls = vec![String::new(); pending.len()];
+    while !pending.is_empty() {
+        let unresolved = pending.iter().map(|x| x.0).collect::<BTreeSet<_>>();
+        let (ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|x| {
+            !is_text(&x.2)
+                || referenced(&x.3)
+                    .iter()
+                    .all(|index| !unresolved.contains(index))
+        });
+        if ready.is_empty() {
+            let paths = waiting
+                .iter()
+                .map(|x| format!("`{}`", x.1))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(mlua::Error::external(format!(
+                "Assets {paths} refer to each other, so they can't contain each other's hash"
+            )));
+        }
+
+        for (index, path, target, content) in ready {
+            let content = if is_text(&target) {
+                replace_placeholders(&content, &urls).unwrap_or(content)
+            } else {
+                content
+            };
+            let target = fingerprint(&target, &content);
+            urls[index] = format!("/{target}");
+            report.output(target.clone(), Some(path), Pipeline::Asset);
+            files.insert(target, content);
+        }
+        pending = waiting;
+    }
+
+    // and point to them
+    if !urls.is_empty() {
+        for (_, content) in files.iter_mut().filter(|x| is_text(x.0)) {
+            if let Some(replaced) = replace_placeholders(content, &urls) {
+                *content = replaced;
+            }
+        }
+    }
+
+    report.stage("assets", start.elapsed());
+
     // set the not found file
     let not_found = if let Some(path) = not_found.take() {
         Some(
@@ -803,5 +1787,64 @@ pub(crate) fn generate(dev: bool) -> Result<Site> {
     };
 
     // done
-    Ok(Site { files, not_found })
+    Ok(Site {
+        files,
+        not_found,
+        report,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let paths = page_paths("blog/page/{n}/index.html", Some("blog/index.html"), 3, true);
+        let urls = paths
+            .unwrap()
+            .iter()
+            .map(|x| page_url(x))
+            .collect::<Vec<_>>();
+        assert_eq!(urls, ["/blog/", "/blog/page/2/", "/blog/page/3/"]);
+
+        let paths = page_paths("tags/{n}.html", None, 2, true).unwrap();
+        assert_eq!(paths, ["tags/1.html", "tags/2.html"]);
+        assert!(page_paths("../{n}.html", None, 1, true).is_err());
+    }
+
+    #[test]
+    fn write_changes() {
+        let output = std::env::temp_dir().join(format!("slsg-write-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&output);
+        let site = |files: &[(&str, &str)]| Site {
+            files: files
+                .iter()
+                .map(|(path, content)| (RelativePathBuf::from(*path), content.as_bytes().to_vec()))
+                .collect(),
+            not_found: None,
+            report: Report::default(),
+        };
+
+        let first = site(&[
+            ("index.html", "a"),
+            ("old.html", "b"),
+            ("posts/x.html", "c"),
+        ]);
+        first.write(&output, None).unwrap();
+
+        // changed on disk, but not in the site, so it's not written again
+        fs::write(output.join("posts/x.html"), "changed").unwrap();
+
+        let second = site(&[("index.html", "new"), ("posts/x.html", "c")]);
+        second.write(&output, Some(&first.files)).unwrap();
+        let index = fs::read_to_string(output.join("index.html")).unwrap();
+        let post = fs::read_to_string(output.join("posts/x.html")).unwrap();
+        let old = output.join("old.html").exists();
+        fs::remove_dir_all(&output).unwrap();
+
+        assert_eq!(index, "new");
+        assert_eq!(post, "changed");
+        assert!(!old);
+    }
 }
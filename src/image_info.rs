@@ -0,0 +1,27 @@
+use std::io::Cursor;
+
+use image::ImageReader;
+
+/// Format, width and height of an image, without decoding all of it
+pub(crate) fn image_info(data: &[u8]) -> Option<(String, u32, u32)> {
+    // images the image crate knows
+    if let Ok(reader) = ImageReader::new(Cursor::new(data)).with_guessed_format()
+        && let Some(format) = reader.format()
+        && let Ok((width, height)) = reader.into_dimensions()
+    {
+        let name = format
+            .extensions_str()
+            .first()
+            .copied()
+            .unwrap_or("unknown");
+        return Some((name.to_string(), width, height));
+    }
+
+    // else, try svg
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default()).ok()?;
+    Some((
+        "svg".to_string(),
+        tree.size().width().ceil() as u32,
+        tree.size().height().ceil() as u32,
+    ))
+}
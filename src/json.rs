@@ -0,0 +1,158 @@
+use mlua::{ExternalResult, Lua, Result, Value};
+use serde_json::{Map, Number};
+
+use crate::data::{Entries, Visited, create_array, entries};
+
+/// Parse json into lua values
+/// null becomes nil in objects, and `null` in arrays so they keep their length
+pub(crate) fn parse_json(lua: &Lua, text: &str, name: &str) -> Result<Value> {
+    let value = serde_json::from_str(text).map_err(|e| {
+        // the position is already in front
+        let message = e.to_string();
+        let message = message.split(" at line ").next().unwrap_or(&message);
+        mlua::Error::external(format!("{name}:{}:{}: {message}", e.line(), e.column()))
+    })?;
+    from_json(lua, value)
+}
+
+/// Turn a json value into a lua value
+fn from_json(lua: &Lua, value: serde_json::Value) -> Result<Value> {
+    Ok(match value {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(bool) => Value::Boolean(bool),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(int) => Value::Integer(int),
+            None => Value::Number(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(string) => Value::String(lua.create_string(string)?),
+        serde_json::Value::Array(values) => {
+            let values = values
+                .into_iter()
+                .map(|x| from_json(lua, x))
+                .collect::<Result<Vec<_>>>()?;
+            Value::Table(create_array(lua, values)?)
+        }
+        serde_json::Value::Object(map) => {
+            let table = lua.create_table()?;
+            for (key, value) in map {
+                table.raw_set(key, from_json(lua, value)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// Serialize a lua value to json
+/// Tables with only the keys 1 to n, or read as arrays, become arrays, and keys are sorted
+pub(crate) fn to_json(lua: &Lua, value: &Value, pretty: bool) -> Result<String> {
+    let value = into_json(lua, value, &mut Visited::default())?;
+    if pretty {
+        serde_json::to_string_pretty(&value).into_lua_err()
+    } else {
+        serde_json::to_string(&value).into_lua_err()
+    }
+}
+
+/// Turn a lua value into a json value
+fn into_json(lua: &Lua, value: &Value, visited: &mut Visited) -> Result<serde_json::Value> {
+    Ok(match value {
+        value if value.is_null() => serde_json::Value::Null,
+        Value::Boolean(bool) => serde_json::Value::Bool(*bool),
+        Value::Integer(int) => serde_json::Value::Number((*int).into()),
+        Value::Number(number) => Number::from_f64(*number)
+            .map(serde_json::Value::Number)
+            .ok_or_else(|| mlua::Error::external(format!("Can't serialize `{number}` to json")))?,
+        Value::String(string) => serde_json::Value::String(string.to_string_lossy()),
+        Value::Table(table) => {
+            visited.enter(table)?;
+            let json = match entries(lua, table)? {
+                Entries::Array(values) => serde_json::Value::Array(
+                    values
+                        .iter()
+                        .map(|x| into_json(lua, x, visited))
+                        .collect::<Result<_>>()?,
+                ),
+                Entries::Map(values) => serde_json::Value::Object(
+                    values
+                        .iter()
+                        .map(|(key, value)| Ok((key.clone(), into_json(lua, value, visited)?)))
+                        .collect::<Result<Map<_, _>>>()?,
+                ),
+            };
+            visited.leave();
+            json
+        }
+        value => {
+            return Err(mlua::Error::external(format!(
+                "Can't serialize a {} to json",
+                value.type_name()
+            )));
+        }
+    })
+}
+
+/// Escape a string to a quoted json string
+pub(crate) fn escape_json(string: &str) -> String {
+    serde_json::Value::from(string).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse and serialize again, without pretty printing
+    fn roundtrip(text: &str) -> Result<String> {
+        let lua = Lua::new();
+        let value = parse_json(&lua, text, "data.json")?;
+        to_json(&lua, &value, false)
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            roundtrip(r#" {"b": [1, -2.5e-3, true, "x\ty"], "a": {}, "c": []} "#).unwrap(),
+            r#"{"a":{},"b":[1,-0.0025,true,"x\ty"],"c":[]}"#
+        );
+        assert_eq!(roundtrip(r#""😀é""#).unwrap(), "\"😀é\"");
+    }
+
+    #[test]
+    fn null() {
+        // nulls keep their place in arrays, but are left out of objects
+        assert_eq!(
+            roundtrip(r#"{"a": null, "b": [null, 1, null]}"#).unwrap(),
+            r#"{"b":[null,1,null]}"#
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = roundtrip("{\n  \"a\": 1,\n}").unwrap_err().to_string();
+        assert!(error.contains("data.json:3:1: trailing comma"), "{error}");
+        assert!(roundtrip("[1, 2").is_err());
+        assert!(roundtrip("\"abc").is_err());
+        assert!(roundtrip("01").is_err());
+        assert!(roundtrip("1 2").is_err());
+
+        let lua = Lua::new();
+        assert!(to_json(&lua, &Value::Number(f64::NAN), false).is_err());
+        let table = lua.create_table().unwrap();
+        table.raw_set("self", &table).unwrap();
+        assert!(to_json(&lua, &Value::Table(table), false).is_err());
+    }
+
+    #[test]
+    fn pretty() {
+        let lua = Lua::new();
+        let value = parse_json(&lua, r#"{"a": [1, {"b": 2}]}"#, "data.json").unwrap();
+        assert_eq!(
+            to_json(&lua, &value, true).unwrap(),
+            "{\n  \"a\": [\n    1,\n    {\n      \"b\": 2\n    }\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_json("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+}
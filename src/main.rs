@@ -6,28 +6,51 @@ use std::{
     time::Instant,
 };
 
+use config::{Config, Flags};
 use generate::generate;
 use mlua::{ErrorContext, ExternalResult, Lua, Result, chunk};
 use print::print_error;
 
 use crate::print::print_success;
 
+mod asset;
+mod bib;
+mod config;
+mod csv;
+mod data;
+mod date;
+mod dom;
 mod font;
 mod generate;
 mod highlight;
 mod html;
+mod image_info;
+mod json;
 mod markdown;
+mod math;
 mod path;
 mod print;
+mod report;
+mod sandbox;
+mod selector;
 mod serve;
 mod templates;
+mod toml;
+mod watch;
+mod wiki;
+mod xml;
+mod yaml;
 
 const HELP: &str = "\
 SLSG - Scriptable Lua Site Generator
 
 Usage:
   slsg dev [path] [--address]   Serve the site in path (default ./)
+           [--profile] [--define] [--safe]
   slsg build [path] [--output]  Build the site in path (default ./)
+             [--report] [--profile] [--define] [--safe]
+  slsg watch [path] [--output]  Rebuild the site in path to disk on changes
+             [--profile] [--define] [--dev] [--safe]
   slsg new <language> [path]    Create a new site in path
   slsg docs                     Show the documentation
   slsg help                     Show this screen
@@ -36,9 +59,15 @@ Options:
   -h --help     Show this screen
   -v --version  Print SLSG and luaJIT version
      --verbose  Print out extra information when building
+     --report   Write a json report of the build to the given file
+     --dev      Watch with `development` set to true
+     --safe     Sandbox site scripts, so they can't access anything outside the site
+  -f --force    Overwrite the output directory, even if it's not empty
 
   -a --address  Where to bind the dev server to (default 127.0.0.1:1111)
-  -o --output   Where to output the files to (default dist/)
+  -o --output   Where to output the files to (default .dist/, or set in site.conf)
+  -p --profile  Profile from site.conf to use for the `config` table
+  -d --define   Set `key=value` in the `config` table, can be repeated
 ";
 
 fn main() {
@@ -84,6 +113,7 @@ fn main() {
     let err = match sub.as_deref() {
         Some("dev") => dev(pargs),
         Some("build") => build(pargs),
+        Some("watch") => watch(pargs),
         Some("new") => new(pargs),
         Some("docs") => print_docs(),
         _ => Ok(println!("{}", HELP)),
@@ -270,58 +300,63 @@ fn find_working_dir(path: &Path) -> Result<&Path> {
         path.parent().ok_or(mlua::Error::external(
             "`site.fnl` does not have a parent directory",
         ))
+    } else if path.file_name() == Some(&OsString::from("site.conf")) {
+        path.parent().ok_or(mlua::Error::external(
+            "`site.conf` does not have a parent directory",
+        ))
     } else {
         for ancestor in path.ancestors() {
-            if ancestor.join("site.lua").exists() || ancestor.join("site.fnl").exists() {
+            if ancestor.join("site.lua").exists()
+                || ancestor.join("site.fnl").exists()
+                || ancestor.join("site.conf").exists()
+            {
                 return Ok(ancestor);
             }
         }
 
         Err(mlua::Error::external(format!(
-            "`site.lua` or `site.fnl` does not exist in `{}` or any of it's ancestors",
+            "`site.lua`, `site.fnl` or `site.conf` does not exist in `{}` or any of it's ancestors",
             path.to_string_lossy()
         )))
     }
 }
 
-/// Build an existing site
-fn build(mut pargs: pico_args::Arguments) -> Result<()> {
-    let current_dir = current_dir()
-        .into_lua_err()
-        .context("could not open current directory")?;
-
-    // parse these first to not get confused with the positional arg
-    let output_path = pargs
-        .opt_value_from_os_str::<_, PathBuf, String>(["-o", "--output"], |x| Ok(PathBuf::from(x)))
-        .into_lua_err()
-        .context("Failed to parse arguments")?;
-
-    // verbose output?
-    let verbose = pargs.contains("--verbose");
-
-    // force clear the directory, only if we are building the current site's ./dist folder
-    // or are passed the --force argument
-    let force_clear = pargs.contains(["-f", "--force"]);
-
-    let path = if let Some(path) = pargs
+/// Find the site path from the free argument, or the working directory if none is given
+fn site_path(pargs: &mut pico_args::Arguments, current_dir: &Path) -> Result<PathBuf> {
+    if let Some(path) = pargs
         .opt_free_from_os_str::<PathBuf, String>(|x| Ok(PathBuf::from(x)))
         .into_lua_err()
         .context("Failed to parse arguments")?
     {
-        path
+        Ok(path)
     } else {
-        find_working_dir(&current_dir)
+        find_working_dir(current_dir)
             .map(|x| x.to_path_buf())
-            .context("Failed to find working directory")?
-    };
+            .context("Failed to find working directory")
+    }
+}
+
+/// Clear and create the output directory, returns the canonical path to it
+fn prepare_output(path: &Path, output_path: Option<PathBuf>, force_clear: bool) -> Result<PathBuf> {
+    // output from the config file, if any
+    let config = Config::load(&path.join("site.conf"))?;
+    let site_output_path = config
+        .output
+        .map(|x| x.to_path(path))
+        .unwrap_or(path.join(".dist"));
 
-    let output_path = output_path.unwrap_or(path.join(".dist"));
+    // flag overrides the config
+    let output_path = output_path.unwrap_or(site_output_path.clone());
+
+    // only clear the output from the config without asking if it's inside the site
+    let inside_site = output_path == site_output_path
+        && output_path
+            .strip_prefix(path)
+            .is_ok_and(|x| x.components().next().is_some());
 
     // clear the output
     // only clear if it's allowed, or it's the output path
-    if force_clear && output_path.is_dir()
-        || output_path.is_dir() && output_path == path.join(".dist")
-    {
+    if force_clear && output_path.is_dir() || output_path.is_dir() && inside_site {
         remove_dir_all(&output_path)
             .into_lua_err()
             .with_context(|_| {
@@ -342,9 +377,6 @@ fn build(mut pargs: pico_args::Arguments) -> Result<()> {
         )));
     }
 
-    // start timing
-    let start = Instant::now();
-
     // make sure the path exists
     create_dir_all(&output_path)
         .into_lua_err()
@@ -356,15 +388,50 @@ fn build(mut pargs: pico_args::Arguments) -> Result<()> {
         })?;
 
     // make it canonical
-    let output_path = output_path
-        .canonicalize()
+    output_path.canonicalize().into_lua_err().with_context(|_| {
+        format!(
+            "Failed to canonicalize output directory path `{}`",
+            output_path.to_string_lossy()
+        )
+    })
+}
+
+/// Build an existing site
+fn build(mut pargs: pico_args::Arguments) -> Result<()> {
+    let current_dir = current_dir()
         .into_lua_err()
-        .with_context(|_| {
-            format!(
-                "Failed to canonicalize output directory path `{}`",
-                output_path.to_string_lossy()
-            )
-        })?;
+        .context("could not open current directory")?;
+
+    // parse these first to not get confused with the positional arg
+    let output_path = pargs
+        .opt_value_from_os_str::<_, PathBuf, String>(["-o", "--output"], |x| Ok(PathBuf::from(x)))
+        .into_lua_err()
+        .context("Failed to parse arguments")?;
+
+    // verbose output?
+    let verbose = pargs.contains("--verbose");
+
+    // profile and variables
+    let flags = Flags::from_args(&mut pargs)?;
+
+    // where to write the build report to, if any
+    let report_path = pargs
+        .opt_value_from_os_str::<_, PathBuf, String>("--report", |x| Ok(PathBuf::from(x)))
+        .into_lua_err()
+        .context("Failed to parse arguments")?
+        .map(|x| current_dir.join(x));
+
+    // force clear the directory, only if we are building the current site's ./dist folder
+    // or are passed the --force argument
+    let force_clear = pargs.contains(["-f", "--force"]);
+
+    let path = site_path(&mut pargs, &current_dir)?;
+
+    // start timing
+    let start = Instant::now();
+
+    // clear the output
+    let output_path = prepare_output(&path, output_path, force_clear)?;
 
     // move to where the main.lua file is
     std::env::set_current_dir(&path)
@@ -372,45 +439,24 @@ fn build(mut pargs: pico_args::Arguments) -> Result<()> {
         .with_context(|_| format!("Failed to change path to `{}`", path.to_string_lossy()))?;
 
     // generate the site,
-    let site = generate(false)?;
-    let mut count = 0;
-    let mut size = 0;
-    for (file_path, contents) in site.files.into_iter() {
-        count += 1;
-        size += contents.len();
-
-        // create the directory for it
-        create_dir_all(
-            &file_path
-                .to_path(&output_path)
-                .parent()
-                .ok_or(mlua::Error::external(format!(
-                    "output path `{}` could not be created",
-                    file_path.to_path(&output_path).to_string_lossy()
-                )))?,
-        )
-        .into_lua_err()
-        .with_context(|_| {
-            format!(
-                "output path `{}` could not be created",
-                file_path.to_path(&output_path).to_string_lossy()
-            )
-        })?;
-
-        // write the file
-        fs::write(file_path.to_path(&output_path), contents)
+    let mut site = generate(false, &flags, Some(&output_path))?;
+    let write_start = Instant::now();
+    site.write(&output_path, None)?;
+
+    // write the report, if asked
+    site.report.stage("write", write_start.elapsed());
+    if let Some(report_path) = report_path {
+        fs::write(&report_path, site.report.to_json(&site.files))
             .into_lua_err()
             .with_context(|_| {
-                format!(
-                    "Failed to write file `{}`",
-                    file_path.to_path(&output_path).to_string_lossy()
-                )
+                format!("Failed to write report `{}`", report_path.to_string_lossy())
             })?;
     }
 
     // report info, if verbose
     if verbose {
-        let size = size as f64 / 1000.0;
+        let count = site.files.len();
+        let size = site.files.values().map(|x| x.len()).sum::<usize>() as f64 / 1000.0;
 
         // pick largest size to use for representation
         // if bigger than one mb, scale down
@@ -440,6 +486,43 @@ fn build(mut pargs: pico_args::Arguments) -> Result<()> {
     Ok(())
 }
 
+/// Rebuild an existing site to disk whenever it changes
+fn watch(mut pargs: pico_args::Arguments) -> Result<()> {
+    let current_dir = current_dir()
+        .into_lua_err()
+        .context("could not open current directory")?;
+
+    // parse these first to not get confused with the positional arg
+    let output_path = pargs
+        .opt_value_from_os_str::<_, PathBuf, String>(["-o", "--output"], |x| Ok(PathBuf::from(x)))
+        .into_lua_err()
+        .context("Failed to parse arguments")?;
+
+    // profile and variables
+    let flags = Flags::from_args(&mut pargs)?;
+
+    // force clear the directory
+    let force_clear = pargs.contains(["-f", "--force"]);
+
+    // build as if we are the development server
+    let dev = pargs.contains("--dev");
+
+    let path = site_path(&mut pargs, &current_dir)?;
+
+    // clear the output
+    let output_path = prepare_output(&path, output_path, force_clear)?;
+
+    // move to where the main.lua file is
+    std::env::set_current_dir(&path)
+        .into_lua_err()
+        .with_context(|_| format!("Failed to change path to `{}`", path.to_string_lossy()))?;
+
+    // keep rebuilding
+    watch::watch(&output_path, &flags, dev)?;
+    println!("Stopped (ctrl-c)");
+    Ok(())
+}
+
 /// Serve an existing site with the development server
 fn dev(mut pargs: pico_args::Arguments) -> Result<()> {
     let addr = pargs
@@ -448,21 +531,14 @@ fn dev(mut pargs: pico_args::Arguments) -> Result<()> {
         .context("Failed to parse arguments")?
         .unwrap_or(String::from("127.0.0.1:1111"));
 
+    // profile and variables
+    let flags = Flags::from_args(&mut pargs)?;
+
     let current_dir = current_dir()
         .into_lua_err()
         .context("could not open current directory")?;
 
-    let path = if let Some(path) = pargs
-        .opt_free_from_os_str::<PathBuf, String>(|x| Ok(PathBuf::from(x)))
-        .into_lua_err()
-        .context("Failed to parse arguments")?
-    {
-        path
-    } else {
-        find_working_dir(&current_dir)
-            .map(|x| x.to_path_buf())
-            .context("Failed to find working directory")?
-    };
+    let path = site_path(&mut pargs, &current_dir)?;
 
     // move to where the main.lua file is
     std::env::set_current_dir(&path)
@@ -470,7 +546,7 @@ fn dev(mut pargs: pico_args::Arguments) -> Result<()> {
         .with_context(|_| format!("Failed to change path to `{}`", path.to_string_lossy()))?;
 
     // run the development server
-    serve::serve(&addr)?;
+    serve::serve(&addr, &flags)?;
     println!("Stopped (ctrl-c)");
     Ok(())
 }
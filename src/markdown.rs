@@ -1,26 +1,53 @@
-use std::{collections::VecDeque, iter::repeat};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs,
+    iter::{Peekable, repeat},
+    slice::Iter,
+};
 
+use glob::Pattern;
 use latex2mathml::latex_to_mathml;
-use mlua::{ErrorContext, ExternalResult, Lua, Result, Value, chunk};
-use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd, html::push_html};
-use relative_path::RelativePath;
+use mlua::{ErrorContext, ExternalResult, Function, Lua, Result, Table, Value, chunk};
+use pulldown_cmark::{
+    Alignment, BlockQuoteKind, BrokenLink, BrokenLinkCallback, CodeBlockKind, CowStr, Event,
+    LinkType, Options, Parser, Tag, TagEnd, html::push_html,
+};
+use relative_path::{RelativePath, RelativePathBuf};
 use unicode_width::UnicodeWidthStr;
 
-use crate::path::{DoubleFileExt, HtmlToIndex};
+use crate::{
+    bib::{Bibliography, parse_citations},
+    data::create_array,
+    image_info::image_info,
+    math::{Macros, references, take_label},
+    path::{DoubleFileExt, Draft, HtmlToIndex},
+    sandbox::site_file,
+    templates::compiled,
+    wiki::Wiki,
+};
 
 /// Parse minimark to html
+/// Also gives the table of contents, and the excerpt as `{ html, text }`
+/// If compile is set, the file is run as a compiled template first, and the output parsed
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn markdown(
     lua: &Lua,
     content: &str,
     name: &RelativePath,
-) -> Result<(String, VecDeque<Value>)> {
+    options: Options,
+    wiki: &Wiki,
+    bibliography: Option<&Bibliography>,
+    macros: &Macros,
+    compile: bool,
+) -> Result<(String, VecDeque<Value>, Table, Table)> {
     // translated name
     let path = name
         .with_extension("html")
         .without_double_ext()
         .ok_or(mlua::Error::external(format!(
             "Expected path `{name}` to have a second `.lua` or `.fnl` extension"
-        )))?;
+        )))?
+        .without_draft();
     let path = path.html_to_index().unwrap_or(path);
 
     // set up environment
@@ -38,6 +65,25 @@ pub(crate) fn markdown(
     lua.globals()
         .set("curtargetdir", path.parent().map(RelativePath::as_str))?;
 
+    // compiled template, run it first so the markdown it outputs can be parsed
+    let (templated, mut functions) = if compile {
+        compiled(lua, content, name)?
+    } else {
+        (String::new(), VecDeque::new())
+    };
+    let content = if compile { &templated } else { content };
+
+    // table of contents
+    let headings = headings(content, options);
+    let toc = toc(lua, &mut headings.iter().peekable(), 0)?;
+    lua.globals().set("curtoc", &toc)?;
+
+    // ids to give to the headings
+    let mut ids = headings.into_iter().map(|x| x.id).collect::<VecDeque<_>>();
+
+    // numbers of the labeled equations
+    let equations = equations(content, options, name.as_str())?;
+
     // events to parse
     let mut events = Vec::new();
 
@@ -49,22 +95,41 @@ pub(crate) fn markdown(
     let mut lang = None;
     let mut pos = None;
 
-    // functions to run after processing
-    let mut functions = VecDeque::new();
+    // citations, which are links without a target, like `[@knuth84]`
+    let mut citing = false;
+    let mut cited = Vec::new();
+    let citation = Citations(bibliography.is_some());
 
     // parse
-    for (event, offset) in Parser::new_ext(
-        content,
-        Options::ENABLE_MATH
-            | Options::ENABLE_FOOTNOTES
-            | Options::ENABLE_STRIKETHROUGH
-            | Options::ENABLE_SMART_PUNCTUATION
-            | Options::ENABLE_HEADING_ATTRIBUTES,
-    )
-    .into_offset_iter()
+    for (event, offset) in
+        Parser::new_with_broken_link_callback(content, options, Some(citation)).into_offset_iter()
     {
         match event {
-            Event::InlineHtml(html) if html.starts_with("<?") && name.has_double_ext("lua") => {
+            // citation, render it instead of the link
+            Event::Start(Tag::Link {
+                link_type: LinkType::ShortcutUnknown,
+                dest_url,
+                ..
+            }) if let Some(bibliography) = bibliography
+                && let Some(citations) = parse_citations(&dest_url) =>
+            {
+                if let Some(unknown) = citations.iter().find(|x| !bibliography.contains(x.key())) {
+                    let line = content[..offset.start].matches('\n').count() + 1;
+                    return Err(mlua::Error::external(format!(
+                        "{name}:{line}: Unknown citation `{}`",
+                        unknown.key()
+                    )));
+                }
+                events.push(Event::InlineHtml(
+                    bibliography.cite(&citations, &mut cited).into(),
+                ));
+                citing = true;
+            }
+            Event::End(TagEnd::Link) if citing => citing = false,
+            _ if citing => (),
+            Event::InlineHtml(html)
+                if html.starts_with("<?") && !compile && name.has_double_ext("lua") =>
+            {
                 let position = offset.start;
                 let lines = content[..position].chars().filter(|x| *x == '\n').count();
                 let width = content[..position]
@@ -109,7 +174,9 @@ pub(crate) fn markdown(
                     functions.push_back(result.clone());
                 }
             }
-            Event::InlineHtml(html) if html.starts_with("<?") && name.has_double_ext("fnl") => {
+            Event::InlineHtml(html)
+                if html.starts_with("<?") && !compile && name.has_double_ext("fnl") =>
+            {
                 let position = offset.start;
                 let lines = content[..position].chars().filter(|x| *x == '\n').count();
                 let width = content[..position]
@@ -168,6 +235,7 @@ pub(crate) fn markdown(
             // run lua
             Event::End(TagEnd::HtmlBlock)
                 if html.as_ref().map(|x| x.starts_with("<?")).unwrap_or(false)
+                    && !compile
                     && name.has_double_ext("lua") =>
             {
                 let position = pos.take().unwrap();
@@ -223,6 +291,7 @@ pub(crate) fn markdown(
             // run fennel
             Event::End(TagEnd::HtmlBlock)
                 if html.as_ref().map(|x| x.starts_with("<?")).unwrap_or(false)
+                    && !compile
                     && name.has_double_ext("fnl") =>
             {
                 let position = pos.take().unwrap();
@@ -284,23 +353,55 @@ pub(crate) fn markdown(
                 events.push(Event::Html(html.take().unwrap().into()));
                 events.push(Event::End(TagEnd::HtmlBlock))
             }
+            // wiki link, point it to the page
+            Event::Start(Tag::Link {
+                link_type: link_type @ LinkType::WikiLink { .. },
+                dest_url,
+                title,
+                id,
+            }) => {
+                let line = content[..offset.start].matches('\n').count() + 1;
+                let url = wiki
+                    .resolve(&path, &dest_url)
+                    .map_err(|e| mlua::Error::external(format!("{name}:{line}: {e}")))?;
+                events.push(Event::Start(Tag::Link {
+                    link_type,
+                    dest_url: url.into(),
+                    title,
+                    id,
+                }))
+            }
+            // heading, give it an id
+            Event::Start(Tag::Heading {
+                level,
+                classes,
+                attrs,
+                ..
+            }) => events.push(Event::Start(Tag::Heading {
+                level,
+                id: ids.pop_front().map(Into::into),
+                classes,
+                attrs,
+            })),
+            // references to equations, link them
+            Event::Text(text)
+                if code.is_none()
+                    && options.contains(Options::ENABLE_MATH)
+                    && text.contains("ref{") =>
+            {
+                let line = content[..offset.start].matches('\n').count() + 1;
+                events.extend(
+                    equation_references(&text, &equations)
+                        .map_err(|e| mlua::Error::external(format!("{name}:{line}: {e}")))?,
+                );
+            }
             // inline math, compile
             Event::InlineMath(mathml) => events.push(Event::InlineHtml(
-                latex_to_mathml(&mathml, latex2mathml::DisplayStyle::Inline)
-                    .into_lua_err()
-                    .with_context(|_| format!("{name}: Failed to compile math"))?
-                    .into(),
+                inline_math(&mathml, macros, name.as_str())?.into(),
             )),
             // display math, compile
             Event::DisplayMath(mathml) => {
-                events.push(Event::Start(Tag::HtmlBlock));
-                events.push(Event::Html(
-                    latex_to_mathml(&mathml, latex2mathml::DisplayStyle::Block)
-                        .into_lua_err()
-                        .with_context(|_| format!("{name}: Failed to compile math"))?
-                        .into(),
-                ));
-                events.push(Event::End(TagEnd::HtmlBlock));
+                events.extend(display_math(&mathml, macros, &equations, name.as_str())?);
             }
             // code, highlight
             Event::Start(Tag::CodeBlock(l)) => {
@@ -312,32 +413,39 @@ pub(crate) fn markdown(
             }
             Event::Text(x) if code.is_some() => code.as_mut().unwrap().push_str(&x),
             Event::End(TagEnd::CodeBlock) => {
-                // highlight
-                let lang = lang.take().unwrap_or("".into());
-                let (lang, prefix) = lang
-                    .split_once(" ")
-                    .map(|x| (x.0, Some(x.1).filter(|x| !x.is_empty())))
-                    .unwrap_or((&lang, None));
-                let code = code.take().unwrap();
-                // easiest to just use the lua function
-                // also works nicely in case it is overridden
-                let highlighted: String = lua
-                    .load(chunk! {highlight($lang, $code, $prefix)})
-                    .set_name(format!("@{name}"))
-                    .eval()?;
-
-                //
-                events.push(Event::Start(Tag::HtmlBlock));
-                events.push(Event::Html(
-                    format!("<pre><code>{}</code></pre>", highlighted).into(),
-                ));
-                events.push(Event::End(TagEnd::HtmlBlock));
+                let (lang, code) = (lang.take(), code.take().unwrap());
+                match fenced_code(lua, lang.as_deref(), &code, name.as_str())? {
+                    Some(fenced) => events.extend(fenced),
+                    None => events.extend(highlight_code(lua, lang, code, name.as_str())?),
+                }
             }
             // rest, just push
             e => events.push(e),
         }
     }
 
+    // add the cited entries
+    if let Some(bibliography) = bibliography
+        && !cited.is_empty()
+    {
+        events.push(Event::Html(bibliography.render(&cited).into()));
+    }
+
+    // let hooks render what they want to
+    let events = render_hooks(lua, events, name.as_str())?;
+
+    // size images, and load them lazily, not the ones the hooks rendered
+    // relative urls are from where the page ends up, as in the browser
+    let events = lazy_images(events, path.parent());
+
+    // excerpt, from what we already rendered
+    let excerpt = excerpt(&events);
+    let mut excerpt_html = String::new();
+    push_html(&mut excerpt_html, excerpt.iter().cloned());
+    let summary = lua.create_table()?;
+    summary.set("html", excerpt_html)?;
+    summary.set("text", excerpt_text(&excerpt))?;
+
     // push out all events
     let mut out = String::with_capacity(content.len());
     push_html(&mut out, events.into_iter());
@@ -347,6 +455,1035 @@ pub(crate) fn markdown(
     lua.globals().set("curdir", false)?;
     lua.globals().set("curtarget", false)?;
     lua.globals().set("curtargetdir", false)?;
+    lua.globals().set("curtoc", false)?;
+
+    Ok((out, functions, toc, summary))
+}
+
+/// Events up to the `<!-- more -->` marker, or of the first paragraph if there is none
+fn excerpt<'a>(events: &[Event<'a>]) -> Vec<Event<'a>> {
+    let marker = events.iter().position(|x| {
+        matches!(x, Event::Html(html) | Event::InlineHtml(html) if html.trim() == "<!-- more -->")
+    });
+    let range = match marker {
+        Some(end) => 0..end,
+        None => {
+            let Some(start) = events
+                .iter()
+                .position(|x| matches!(x, Event::Start(Tag::Paragraph)))
+            else {
+                return Vec::new();
+            };
+            let end = events[start..]
+                .iter()
+                .position(|x| matches!(x, Event::End(TagEnd::Paragraph)))
+                .map(|x| start + x + 1)
+                .unwrap_or(events.len());
+            start..end
+        }
+    };
+
+    // close the elements the marker was in
+    let mut excerpt = events[range].to_vec();
+    let mut open = Vec::new();
+    for event in &excerpt {
+        match event {
+            Event::Start(tag) => open.push(tag.to_end()),
+            Event::End(_) => {
+                open.pop();
+            }
+            _ => (),
+        }
+    }
+    excerpt.extend(open.into_iter().rev().map(Event::End));
+    excerpt
+}
+
+/// Plain text of the excerpt
+fn excerpt_text(events: &[Event]) -> String {
+    let mut text = String::new();
+    for event in events {
+        match event {
+            Event::Text(x) | Event::Code(x) => text.push_str(x),
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item) => text.push(' '),
+            _ => (),
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Turns links without a target into citations, if they look like one and there is a bibliography
+struct Citations(bool);
+
+impl<'input> BrokenLinkCallback<'input> for Citations {
+    fn handle_broken_link(
+        &mut self,
+        link: BrokenLink<'input>,
+    ) -> Option<(CowStr<'input>, CowStr<'input>)> {
+        (self.0 && parse_citations(&link.reference).is_some()).then(|| (link.reference, "".into()))
+    }
+}
+
+/// Render markdown to html, without running any code in it
+pub(crate) fn render_markdown(
+    lua: &Lua,
+    content: &str,
+    options: Options,
+    highlight: bool,
+    macros: &Macros,
+) -> Result<String> {
+    // name to use in errors
+    let name = "rendermarkdown";
+
+    // events to parse
+    let mut events = Vec::new();
+
+    // code we might encounter
+    let mut code = None;
+    let mut lang = None;
+
+    // numbers of the labeled equations
+    let equations = equations(content, options, name)?;
+
+    // whether we are in a code block, captured or not
+    let mut in_code = false;
+
+    for (event, offset) in Parser::new_ext(content, options).into_offset_iter() {
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(TagEnd::CodeBlock) => in_code = false,
+            _ => (),
+        }
+
+        match event {
+            // references to equations, link them
+            Event::Text(text)
+                if !in_code && options.contains(Options::ENABLE_MATH) && text.contains("ref{") =>
+            {
+                let line = content[..offset.start].matches('\n').count() + 1;
+                events.extend(
+                    equation_references(&text, &equations)
+                        .map_err(|e| mlua::Error::external(format!("{name}:{line}: {e}")))?,
+                );
+            }
+            // inline math, compile
+            Event::InlineMath(mathml) => events.push(Event::InlineHtml(
+                inline_math(&mathml, macros, name)?.into(),
+            )),
+            // display math, compile
+            Event::DisplayMath(mathml) => {
+                events.extend(display_math(&mathml, macros, &equations, name)?)
+            }
+            // code, highlight, or render it with a fence handler
+            Event::Start(Tag::CodeBlock(l)) if highlight || has_fence(lua, &l)? => {
+                lang = match l {
+                    CodeBlockKind::Indented => None,
+                    CodeBlockKind::Fenced(l) => Some(l.to_string()),
+                };
+                code = Some(String::new());
+            }
+            Event::Text(x) if code.is_some() => code.as_mut().unwrap().push_str(&x),
+            Event::End(TagEnd::CodeBlock) if code.is_some() => {
+                let (lang, code) = (lang.take(), code.take().unwrap());
+                match fenced_code(lua, lang.as_deref(), &code, name)? {
+                    Some(fenced) => events.extend(fenced),
+                    None => events.extend(highlight_code(lua, lang, code, name)?),
+                }
+            }
+            // rest, just push
+            e => events.push(e),
+        }
+    }
+
+    // let hooks render what they want to
+    let events = render_hooks(lua, events, name)?;
+
+    // size images, and load them lazily, not the ones the hooks rendered
+    // relative urls are from where the current file ends up, if any
+    let target_dir = match lua.globals().get::<Value>("curtargetdir")? {
+        Value::String(dir) => Some(RelativePathBuf::from(dir.to_str()?.to_string())),
+        _ => None,
+    };
+    let events = lazy_images(events, target_dir.as_deref());
+
+    // push out all events
+    let mut out = String::with_capacity(content.len());
+    push_html(&mut out, events.into_iter());
+
+    Ok(out)
+}
+
+/// Elements that can be rendered with a hook
+const HOOKS: [&str; 5] = ["link", "image", "heading", "blockquote", "table"];
+
+/// Set the function to render an element with, or remove it if there is none
+pub(crate) fn set_render_hook(lua: &Lua, kind: &str, hook: Option<Function>) -> Result<()> {
+    if !HOOKS.contains(&kind) {
+        return Err(mlua::Error::external(format!(
+            "Can't add a render hook for `{kind}`, expected one of {}",
+            HOOKS.map(|x| format!("`{x}`")).join(", ")
+        )));
+    }
+
+    registry_table(lua, "slsg.renderhooks")?.set(kind, hook)
+}
+
+/// Set the function to render fenced code blocks of the language with, or remove it if there is none
+pub(crate) fn set_fence(lua: &Lua, lang: &str, handler: Option<Function>) -> Result<()> {
+    registry_table(lua, "slsg.fences")?.set(lang, handler)
+}
+
+/// Table in the registry, made if it doesn't exist yet
+fn registry_table(lua: &Lua, name: &str) -> Result<Table> {
+    if let Some(table) = lua.named_registry_value::<Option<Table>>(name)? {
+        return Ok(table);
+    }
+
+    let table = lua.create_table()?;
+    lua.set_named_registry_value(name, &table)?;
+    Ok(table)
+}
+
+/// Handler for the language of a fenced code block, if any
+fn fence_handler(lua: &Lua, lang: &str) -> Result<Option<Function>> {
+    match lua.named_registry_value::<Option<Table>>("slsg.fences")? {
+        Some(fences) => fences.get(lang),
+        None => Ok(None),
+    }
+}
+
+/// Whether the code block has a fence handler
+fn has_fence(lua: &Lua, kind: &CodeBlockKind) -> Result<bool> {
+    match kind {
+        CodeBlockKind::Indented => Ok(false),
+        CodeBlockKind::Fenced(info) => {
+            let lang = info.split_once(' ').map(|x| x.0).unwrap_or(info);
+            Ok(fence_handler(lua, lang)?.is_some())
+        }
+    }
+}
+
+/// Render a fenced code block with the handler for its language
+/// The handler gets the code and the rest of the info string, and can return nil to highlight it instead
+fn fenced_code(
+    lua: &Lua,
+    info: Option<&str>,
+    code: &str,
+    name: &str,
+) -> Result<Option<[Event<'static>; 3]>> {
+    let Some(info) = info else {
+        return Ok(None);
+    };
+    let (lang, rest) = info.split_once(' ').unwrap_or((info, ""));
+    let Some(handler) = fence_handler(lua, lang)? else {
+        return Ok(None);
+    };
+
+    let html: Option<String> = handler
+        .call((code, rest))
+        .with_context(|_| format!("{name}: Failed to render `{lang}` block"))?;
+
+    Ok(html.map(|html| {
+        [
+            Event::Start(Tag::HtmlBlock),
+            Event::Html(html.into()),
+            Event::End(TagEnd::HtmlBlock),
+        ]
+    }))
+}
+
+/// Replace the elements that have a render hook with what the hook returns
+/// Hooks get `{ attrs, html, text }`, and can return nil to render the element normally
+fn render_hooks<'a>(lua: &Lua, events: Vec<Event<'a>>, name: &str) -> Result<Vec<Event<'a>>> {
+    let Some(hooks) = lua.named_registry_value::<Option<Table>>("slsg.renderhooks")? else {
+        return Ok(events);
+    };
+
+    let mut out = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        let (tag, kind, hook) = match event {
+            Event::Start(tag)
+                if let Some(kind) = hook_kind(&tag)
+                    && let Some(hook) = hooks.get::<Option<Function>>(kind)? =>
+            {
+                (tag, kind, hook)
+            }
+            event => {
+                out.push(event);
+                continue;
+            }
+        };
+
+        // everything up to the matching end
+        let mut inner = Vec::new();
+        let mut end = None;
+        let mut depth = 0;
+        for event in events.by_ref() {
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(x) if depth == 0 => {
+                    end = Some(x);
+                    break;
+                }
+                Event::End(_) => depth -= 1,
+                _ => (),
+            }
+            inner.push(event);
+        }
+
+        // inner elements can have hooks as well
+        let inner = render_hooks(lua, inner, name)?;
+        let element = hook_element(lua, &tag, &inner)?;
+        let html: Option<String> = hook
+            .call(element)
+            .with_context(|_| format!("{name}: Failed to render {kind} with render hook"))?;
+
+        match html {
+            Some(html) if matches!(tag, Tag::Link { .. } | Tag::Image { .. }) => {
+                out.push(Event::InlineHtml(html.into()))
+            }
+            Some(html) => out.push(Event::Html(html.into())),
+            None => {
+                out.push(Event::Start(tag));
+                out.extend(inner);
+                out.extend(end.map(Event::End));
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Turn images into an `<img>` that loads lazily, with the size if it's a file in the site
+/// Relative urls are looked up from dir, the directory the page is emitted to, if any
+fn lazy_images<'a>(events: Vec<Event<'a>>, dir: Option<&RelativePath>) -> Vec<Event<'a>> {
+    let mut out = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::Image {
+            dest_url, title, ..
+        }) = event
+        else {
+            out.push(event);
+            continue;
+        };
+
+        // alt text, up to the matching end
+        let mut alt = String::new();
+        let mut depth = 0;
+        for event in events.by_ref() {
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => break,
+                Event::End(_) => depth -= 1,
+                Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+                Event::SoftBreak | Event::HardBreak => alt.push(' '),
+                _ => (),
+            }
+        }
+
+        let mut html = format!(
+            "<img src=\"{}\" alt=\"{}\"",
+            escape_html(&dest_url),
+            escape_html(&alt)
+        );
+        if !title.is_empty() {
+            html.push_str(&format!(" title=\"{}\"", escape_html(&title)));
+        }
+        if let Some((width, height)) = image_size(&dest_url, dir) {
+            html.push_str(&format!(" width=\"{width}\" height=\"{height}\""));
+        }
+        html.push_str(" loading=\"lazy\" decoding=\"async\" />");
+        out.push(Event::InlineHtml(html.into()));
+    }
+
+    out
+}
+
+/// Width and height of the image at url, if it's a file in the site
+fn image_size(url: &str, dir: Option<&RelativePath>) -> Option<(u32, u32)> {
+    // no query or fragment
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+
+    // not a local file
+    if url.is_empty() || url.starts_with("//") || url.split('/').next()?.contains(':') {
+        return None;
+    }
+
+    let path = match url.strip_prefix('/') {
+        Some(url) => RelativePath::new(url).normalize(),
+        None => dir?.join_normalized(url),
+    };
+
+    // files are emitted to the same path, so the url is also the path of the source
+    // outside the site, also through symlinks
+    let path = site_file(path.as_str(), true).ok()?;
+    let data = fs::read(path.to_path(".")).ok()?;
+    image_info(&data).map(|(_, width, height)| (width, height))
+}
+
+/// Escape html
+fn escape_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    for c in html.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Markdown extension by name
+pub(crate) fn extension(name: &str) -> Option<Options> {
+    match name {
+        "math" => Some(Options::ENABLE_MATH),
+        "footnotes" => Some(Options::ENABLE_FOOTNOTES),
+        "strikethrough" => Some(Options::ENABLE_STRIKETHROUGH),
+        "smartpunctuation" => Some(Options::ENABLE_SMART_PUNCTUATION),
+        "headingattributes" => Some(Options::ENABLE_HEADING_ATTRIBUTES),
+        "tables" => Some(Options::ENABLE_TABLES),
+        "tasklists" => Some(Options::ENABLE_TASKLISTS),
+        "definitionlists" => Some(Options::ENABLE_DEFINITION_LIST),
+        "alerts" => Some(Options::ENABLE_GFM),
+        "wikilinks" => Some(Options::ENABLE_WIKILINKS),
+        "superscript" => Some(Options::ENABLE_SUPERSCRIPT),
+        "subscript" => Some(Options::ENABLE_SUBSCRIPT),
+        _ => None,
+    }
+}
+
+/// Markdown extensions to use for the file at path, or all files if there is none
+/// Later changes override earlier ones, and changes with a glob only apply to matching files
+pub(crate) fn options(
+    changes: &[(Option<Pattern>, Options, bool)],
+    path: Option<&RelativePath>,
+) -> Options {
+    let mut options = Options::ENABLE_MATH
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_SMART_PUNCTUATION
+        | Options::ENABLE_HEADING_ATTRIBUTES;
+    for (glob, extension, enabled) in changes {
+        let applies = match (glob, path) {
+            (None, _) => true,
+            (Some(glob), Some(path)) => glob.matches(path.as_str()),
+            (Some(_), None) => false,
+        };
+        if applies {
+            options.set(*extension, *enabled);
+        }
+    }
+    options
+}
+
+/// Compile inline math to mathml
+fn inline_math(mathml: &str, macros: &Macros, name: &str) -> Result<String> {
+    let mathml = macros
+        .expand(mathml)
+        .with_context(|_| format!("{name}: Failed to compile math"))?;
+    latex_to_mathml(&mathml, latex2mathml::DisplayStyle::Inline)
+        .into_lua_err()
+        .with_context(|_| format!("{name}: Failed to compile math"))
+}
+
+/// Compile display math to a mathml block
+/// Math with a `\label{...}` gets its number from equations, and an id to link to
+fn display_math(
+    mathml: &str,
+    macros: &Macros,
+    equations: &BTreeMap<String, usize>,
+    name: &str,
+) -> Result<[Event<'static>; 3]> {
+    let (label, mathml) = take_label(mathml);
+    let mathml = macros
+        .expand(&mathml)
+        .with_context(|_| format!("{name}: Failed to compile math"))?;
+    let mathml = latex_to_mathml(&mathml, latex2mathml::DisplayStyle::Block)
+        .into_lua_err()
+        .with_context(|_| format!("{name}: Failed to compile math"))?;
+
+    // numbered equation
+    let html = match label.and_then(|x| Some((x, equations.get(x)?))) {
+        Some((label, number)) => format!(
+            "<div class=\"equation\" id=\"{}\">{mathml}<span class=\"equation-number\">({number})</span></div>",
+            escape_html(label)
+        ),
+        None => mathml,
+    };
+
+    Ok([
+        Event::Start(Tag::HtmlBlock),
+        Event::Html(html.into()),
+        Event::End(TagEnd::HtmlBlock),
+    ])
+}
+
+/// Number the display math with a `\label{...}`, in order
+fn equations(content: &str, options: Options, name: &str) -> Result<BTreeMap<String, usize>> {
+    let mut equations = BTreeMap::new();
+    for (event, offset) in Parser::new_ext(content, options).into_offset_iter() {
+        if let Event::DisplayMath(mathml) = event
+            && let (Some(label), _) = take_label(&mathml)
+        {
+            let number = equations.len() + 1;
+            if equations.insert(label.to_string(), number).is_some() {
+                let line = content[..offset.start].matches('\n').count() + 1;
+                return Err(mlua::Error::external(format!(
+                    "{name}:{line}: Equation label `{label}` is used more than once"
+                )));
+            }
+        }
+    }
+
+    Ok(equations)
+}
+
+/// Turn `\eqref{...}` and `\ref{...}` in text into links to the equation
+fn equation_references<'a>(
+    text: &str,
+    equations: &BTreeMap<String, usize>,
+) -> std::result::Result<Vec<Event<'a>>, String> {
+    let mut events = Vec::new();
+    let mut last = 0;
+    for reference in references(text) {
+        let number = equations.get(reference.label).ok_or_else(|| {
+            format!(
+                "Reference to equation `{}`, which does not exist",
+                reference.label
+            )
+        })?;
+        let number = if reference.parens {
+            format!("({number})")
+        } else {
+            number.to_string()
+        };
+
+        if last < reference.start {
+            events.push(Event::Text(text[last..reference.start].to_string().into()));
+        }
+        events.push(Event::InlineHtml(
+            format!(
+                "<a href=\"#{}\" class=\"equation-ref\">{number}</a>",
+                escape_html(reference.label)
+            )
+            .into(),
+        ));
+        last = reference.end;
+    }
+
+    if last < text.len() {
+        events.push(Event::Text(text[last..].to_string().into()));
+    }
+    Ok(events)
+}
+
+/// Highlight a code block
+fn highlight_code(
+    lua: &Lua,
+    lang: Option<String>,
+    code: String,
+    name: &str,
+) -> Result<[Event<'static>; 3]> {
+    let lang = lang.unwrap_or_default();
+    let (lang, prefix) = lang
+        .split_once(" ")
+        .map(|x| (x.0, Some(x.1).filter(|x| !x.is_empty())))
+        .unwrap_or((&lang, None));
+
+    // easiest to just use the lua function
+    // also works nicely in case it is overridden
+    let highlighted: String = lua
+        .load(chunk! {highlight($lang, $code, $prefix)})
+        .set_name(format!("@{name}"))
+        .eval()?;
+
+    Ok([
+        Event::Start(Tag::HtmlBlock),
+        Event::Html(format!("<pre><code>{}</code></pre>", highlighted).into()),
+        Event::End(TagEnd::HtmlBlock),
+    ])
+}
+
+/// Heading in a markdown file
+struct Heading {
+    level: usize,
+    id: String,
+    text: String,
+}
+
+/// Id and text of all headings
+pub(crate) fn heading_ids(content: &str, options: Options) -> Vec<(String, String)> {
+    headings(content, options)
+        .into_iter()
+        .map(|x| (x.id, x.text))
+        .collect()
+}
+
+/// Find all headings, and give the ones without an id an unique id
+fn headings(content: &str, options: Options) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut current = None;
+    for event in Parser::new_ext(content, options) {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some((level as usize, id.map(|x| x.to_string()), String::new()))
+            }
+            Event::Text(text) | Event::Code(text) | Event::InlineMath(text) => {
+                if let Some((_, _, current)) = current.as_mut() {
+                    current.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(heading) = current.take() {
+                    headings.push(heading);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // ids that were set by hand are already taken
+    let mut used = headings
+        .iter()
+        .filter_map(|x| x.1.clone())
+        .collect::<BTreeSet<_>>();
+
+    headings
+        .into_iter()
+        .map(|(level, id, text)| {
+            let id = id.unwrap_or_else(|| {
+                // add a number if the slug is already used
+                let slug = slugify(&text);
+                let mut id = slug.clone();
+                let mut i = 1;
+                while used.contains(&id) {
+                    id = format!("{slug}-{i}");
+                    i += 1;
+                }
+                used.insert(id.clone());
+                id
+            });
+            Heading { level, id, text }
+        })
+        .collect()
+}
+
+/// Turn text into an id
+pub(crate) fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Make the table of contents, from all headings below the given level
+fn toc(lua: &Lua, headings: &mut Peekable<Iter<Heading>>, level: usize) -> Result<Table> {
+    let mut entries = Vec::new();
+    while let Some(heading) = headings.next_if(|x| x.level > level) {
+        let entry = lua.create_table()?;
+        entry.set("level", heading.level)?;
+        entry.set("id", heading.id.as_str())?;
+        entry.set("text", heading.text.as_str())?;
+        entry.set("children", toc(lua, headings, heading.level)?)?;
+        entries.push(Value::Table(entry));
+    }
+
+    create_array(lua, entries)
+}
+
+/// Name of the hook for the element, if it can have one
+fn hook_kind(tag: &Tag) -> Option<&'static str> {
+    match tag {
+        Tag::Link { .. } => Some("link"),
+        Tag::Image { .. } => Some("image"),
+        Tag::Heading { .. } => Some("heading"),
+        Tag::BlockQuote(_) => Some("blockquote"),
+        Tag::Table(_) => Some("table"),
+        _ => None,
+    }
+}
+
+/// Table given to a render hook
+fn hook_element(lua: &Lua, tag: &Tag, inner: &[Event]) -> Result<Table> {
+    let element = lua.create_table()?;
+    let attrs = lua.create_table()?;
+
+    // plain text inside the element
+    let text = inner
+        .iter()
+        .filter_map(|x| match x {
+            Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect::<String>();
+
+    // html inside the element
+    let mut html = String::new();
+    match tag {
+        Tag::Link {
+            dest_url, title, ..
+        } => {
+            attrs.set("href", dest_url.as_ref())?;
+            attrs.set("title", Some(title.as_ref()).filter(|x| !x.is_empty()))?;
+        }
+        Tag::Image {
+            dest_url, title, ..
+        } => {
+            attrs.set("src", dest_url.as_ref())?;
+            attrs.set("alt", text.as_str())?;
+            attrs.set("title", Some(title.as_ref()).filter(|x| !x.is_empty()))?;
+        }
+        Tag::Heading {
+            level,
+            id,
+            classes,
+            attrs: extra,
+        } => {
+            element.set("level", *level as usize)?;
+            attrs.set("id", id.as_deref())?;
+            if !classes.is_empty() {
+                attrs.set("class", classes.join(" "))?;
+            }
+            for (key, value) in extra {
+                attrs.set(key.as_ref(), value.as_deref().unwrap_or(""))?;
+            }
+        }
+        Tag::BlockQuote(Some(kind)) => {
+            let kind = match kind {
+                BlockQuoteKind::Note => "note",
+                BlockQuoteKind::Tip => "tip",
+                BlockQuoteKind::Important => "important",
+                BlockQuoteKind::Warning => "warning",
+                BlockQuoteKind::Caution => "caution",
+            };
+            element.set("alert", kind)?;
+            attrs.set("class", format!("markdown-alert-{kind}"))?;
+        }
+        Tag::Table(alignments) => {
+            let align = alignments.iter().map(|x| match x {
+                Alignment::None => "none",
+                Alignment::Left => "left",
+                Alignment::Center => "center",
+                Alignment::Right => "right",
+            });
+            element.set("align", lua.create_sequence_from(align)?)?;
+
+            // cells need to know the alignment, so render the whole table
+            push_html(
+                &mut html,
+                [Event::Start(tag.clone())]
+                    .into_iter()
+                    .chain(inner.iter().cloned())
+                    .chain([Event::End(tag.to_end())]),
+            );
+            html = html
+                .strip_prefix("<table>")
+                .and_then(|x| x.trim_end().strip_suffix("</table>"))
+                .unwrap_or(&html)
+                .to_string();
+        }
+        _ => (),
+    }
+
+    if html.is_empty() {
+        push_html(&mut html, inner.iter().cloned());
+    }
+
+    element.set("attrs", attrs)?;
+    element.set("html", html)?;
+    element.set("text", text)?;
+    Ok(element)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render markdown with math
+    fn render_math(content: &str, options: Options) -> Result<String> {
+        render_markdown(&Lua::new(), content, options, false, &Macros::default())
+    }
 
-    Ok((out, functions))
+    #[test]
+    fn equation_numbers() {
+        let content =
+            "$$a \\label{one}$$\n\n$$b$$\n\n$$c \\label{two}$$\n\nsee \\eqref{two}, \\ref{one}";
+        let html = render_math(content, Options::ENABLE_MATH).unwrap();
+        assert!(html.contains("<div class=\"equation\" id=\"one\">"));
+        assert!(html.contains("<span class=\"equation-number\">(2)</span></div>"));
+        assert!(html.contains(concat!(
+            "see <a href=\"#two\" class=\"equation-ref\">(2)</a>, ",
+            "<a href=\"#one\" class=\"equation-ref\">1</a>"
+        )));
+    }
+
+    #[test]
+    fn unknown_references() {
+        // no such label
+        let error = render_math(
+            "$$a \\label{one}$$\n\nsee\n\\ref{two}",
+            Options::ENABLE_MATH,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.contains("rendermarkdown:4: Reference to equation `two`, which does not exist")
+        );
+
+        // no math, so no references either
+        let html = render_math("see \\eqref{one}", Options::empty()).unwrap();
+        assert_eq!(html, "<p>see \\eqref{one}</p>\n");
+
+        let content = "$$a \\label{one}$$\n\n$$b \\label{one}$$";
+        assert!(render_math(content, Options::ENABLE_MATH).is_err());
+    }
+
+    #[test]
+    fn render() {
+        let lua = Lua::new();
+        let html = render_markdown(
+            &lua,
+            "# Hi\n\n*a* -- b\n\n```\n\\ref{x} $y$\n```\n\n![i](i.png)",
+            options(&[], None),
+            false,
+            &Macros::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            html,
+            concat!(
+                "<h1>Hi</h1>\n<p><em>a</em> – b</p>\n<pre><code>\\ref{x} $y$\n</code></pre>\n",
+                "<p><img src=\"i.png\" alt=\"i\" loading=\"lazy\" decoding=\"async\" /></p>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(
+            slugify("  snake_case -- and  spaces "),
+            "snake-case-and-spaces"
+        );
+        assert_eq!(slugify("Ünïcödé 日本"), "ünïcödé-日本");
+        assert_eq!(slugify("?!"), "section");
+    }
+
+    #[test]
+    fn heading_ids_are_unique() {
+        let content = "# Intro\n## `code` and $x$\n# Intro\n# Custom {#intro-1}\n# Intro";
+        assert_eq!(
+            heading_ids(content, options(&[], None)),
+            [
+                ("intro", "Intro"),
+                ("code-and-x", "code and x"),
+                ("intro-2", "Intro"),
+                ("intro-1", "Custom"),
+                ("intro-3", "Intro"),
+            ]
+            .map(|(id, text)| (id.to_string(), text.to_string()))
+        );
+    }
+
+    #[test]
+    fn image_sizes() {
+        let dir = RelativePath::new("examples/template-lua");
+        assert_eq!(image_size("icon.svg", Some(dir)), Some((100, 100)));
+        assert_eq!(image_size("icon.svg?v=1#x", Some(dir)), Some((100, 100)));
+        assert_eq!(
+            image_size("/examples/template-lua/icon.svg", None),
+            Some((100, 100))
+        );
+        assert_eq!(image_size("icon.svg", None), None);
+        assert_eq!(image_size("../../icon.svg", Some(dir)), None);
+        assert_eq!(image_size("https://example.com/icon.svg", Some(dir)), None);
+        assert_eq!(image_size("//example.com/icon.svg", Some(dir)), None);
+    }
+
+    #[test]
+    fn lazy() {
+        let events = Parser::new("![an *icon*](icon.svg \"a \\\"title\\\"\") ![x](<b&c.png>)")
+            .collect::<Vec<_>>();
+        let events = lazy_images(events, Some(RelativePath::new("examples/template-lua")));
+        let mut html = String::new();
+        push_html(&mut html, events.into_iter());
+        assert_eq!(
+            html,
+            concat!(
+                "<p><img src=\"icon.svg\" alt=\"an icon\" title=\"a &quot;title&quot;\" ",
+                "width=\"100\" height=\"100\" loading=\"lazy\" decoding=\"async\" /> ",
+                "<img src=\"b&amp;c.png\" alt=\"x\" loading=\"lazy\" decoding=\"async\" /></p>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        let tables = extension("tables").unwrap();
+        let math = extension("math").unwrap();
+        assert_eq!(extension("nope"), None);
+
+        // defaults, then changes in order, with globs only for matching files
+        let changes = [
+            (None, tables, true),
+            (Some(Pattern::new("notes/**").unwrap()), math, false),
+            (Some(Pattern::new("notes/raw/*").unwrap()), tables, false),
+            (Some(Pattern::new("notes/raw/*").unwrap()), math, true),
+        ];
+        let path = |x| Some(RelativePath::new(x));
+        assert!(options(&[], None).contains(math));
+        assert!(!options(&[], None).contains(tables));
+        assert!(options(&changes, None).contains(tables | math));
+        assert!(options(&changes, path("index.md")).contains(tables | math));
+        assert!(!options(&changes, path("notes/a.md")).contains(math));
+        assert!(options(&changes, path("notes/a.md")).contains(tables));
+        assert!(options(&changes, path("notes/raw/a.md")).contains(math));
+        assert!(!options(&changes, path("notes/raw/a.md")).contains(tables));
+    }
+
+    #[test]
+    fn hooks() {
+        let lua = Lua::new();
+        let (link, heading) = lua
+            .load(
+                r#"
+                local function link(x)
+                    if x.attrs.href:find("^http") then return "<b>" .. x.html .. "</b>" end
+                end
+                local function heading(x)
+                    return ("<h%d id=%s>%s</h%d>"):format(x.level, x.attrs.id, x.html, x.level)
+                end
+                return link, heading
+                "#,
+            )
+            .eval()
+            .unwrap();
+        set_render_hook(&lua, "link", Some(link)).unwrap();
+        set_render_hook(&lua, "heading", Some(heading)).unwrap();
+        assert!(set_render_hook(&lua, "paragraph", None).is_err());
+
+        // hooks run inside other hooks, and nil keeps the element
+        let content = "# *Hi* [there](https://example.com) {#hi}\n\n[a *b*](a.md) [c](http://c)";
+        let events = Parser::new_ext(content, Options::ENABLE_HEADING_ATTRIBUTES).collect();
+        let events = render_hooks(&lua, events, "test.md").unwrap();
+        let mut html = String::new();
+        push_html(&mut html, events.into_iter());
+        assert_eq!(
+            html,
+            concat!(
+                "<h1 id=hi><em>Hi</em> <b>there</b></h1>\n",
+                "<p><a href=\"a.md\">a <em>b</em></a> <b>c</b></p>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn hook_kinds() {
+        let kinds = Parser::new_ext(
+            "# a\n\n> [!NOTE]\n> b\n\n|c|\n|-|\n\n[d](e) ![f](g)",
+            Options::all(),
+        )
+        .filter_map(|x| match x {
+            Event::Start(tag) => hook_kind(&tag),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(kinds, ["heading", "blockquote", "table", "link", "image"]);
+    }
+
+    #[test]
+    fn fences() {
+        let lua = Lua::new();
+        let (upper, skip) = lua
+            .load(
+                r#"
+                local function upper(code, rest) return "<pre>" .. code:upper() .. rest .. "</pre>" end
+                local function skip() return nil end
+                return upper, skip
+                "#,
+            )
+            .eval()
+            .unwrap();
+        set_fence(&lua, "upper", Some(upper)).unwrap();
+        set_fence(&lua, "skip", Some(skip)).unwrap();
+
+        let fenced = |info: &str| has_fence(&lua, &CodeBlockKind::Fenced(info.into())).unwrap();
+        assert!(fenced("upper {.wide}"));
+        assert!(fenced("skip"));
+        assert!(!fenced("rust"));
+        assert!(!has_fence(&lua, &CodeBlockKind::Indented).unwrap());
+
+        // the handler gets the rest of the info string, and nil means highlight it
+        let events = fenced_code(&lua, Some("upper x y"), "a\n", "test.md").unwrap();
+        assert!(matches!(events, Some([_, Event::Html(html), _]) if &*html == "<pre>A\nx y</pre>"));
+        assert!(
+            fenced_code(&lua, Some("skip"), "a", "test.md")
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            fenced_code(&lua, Some("rust"), "a", "test.md")
+                .unwrap()
+                .is_none()
+        );
+        assert!(fenced_code(&lua, None, "a", "test.md").unwrap().is_none());
+
+        // removing it
+        set_fence(&lua, "upper", None).unwrap();
+        assert!(!fenced("upper"));
+    }
+
+    #[test]
+    fn excerpts() {
+        let summary = |content| {
+            let events = Parser::new(content).collect::<Vec<_>>();
+            let excerpt = excerpt(&events);
+            let mut html = String::new();
+            push_html(&mut html, excerpt.iter().cloned());
+            (html, excerpt_text(&excerpt))
+        };
+
+        // first paragraph
+        assert_eq!(
+            summary("# Title\n\nFirst *para*\ngraph.\n\nSecond."),
+            (
+                "<p>First <em>para</em>\ngraph.</p>\n".into(),
+                "First para graph.".into()
+            )
+        );
+
+        // up to the marker, closing what it's in
+        assert_eq!(
+            summary("# Title\n\nIntro `code`.\n\n<!-- more -->\n\nRest."),
+            (
+                "<h1>Title</h1>\n<p>Intro <code>code</code>.</p>\n".into(),
+                "Title Intro code.".into()
+            )
+        );
+        assert_eq!(
+            summary("- a <!-- more --> b\n- c"),
+            ("<ul>\n<li>a </li>\n</ul>\n".into(), "a".into())
+        );
+
+        assert_eq!(summary("# Only a title"), (String::new(), String::new()));
+    }
 }
@@ -0,0 +1,470 @@
+use std::collections::BTreeMap;
+
+use mlua::Result;
+
+/// How often macros can expand into other macros, before giving up
+const MAX_DEPTH: usize = 64;
+
+/// How many times longer than the tex, or 1024 bytes, the expanded tex can be
+const MAX_GROWTH: usize = 64;
+
+/// TeX macros, to expand before compiling math
+#[derive(Default)]
+pub(crate) struct Macros(BTreeMap<String, Macro>);
+
+/// Single macro
+struct Macro {
+    /// Number of arguments
+    args: usize,
+
+    /// Default for the first argument, which makes it optional
+    default: Option<String>,
+
+    /// What the macro expands to, with `#1` to `#9` for the arguments
+    body: String,
+}
+
+impl Macros {
+    /// Parse the `\newcommand`, `\renewcommand`, `\def` and `\DeclareMathOperator`
+    /// definitions in a preamble
+    pub(crate) fn parse(text: &str, name: &str) -> Result<Self> {
+        // remove comments
+        let text = text
+            .lines()
+            .map(|x| {
+                let comment = x
+                    .char_indices()
+                    .find(|(i, c)| *c == '%' && !escaped(&x[..*i]))
+                    .map(|(i, _)| i);
+                &x[..comment.unwrap_or(x.len())]
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut macros = BTreeMap::new();
+        let mut rest = text.trim_start();
+        while !rest.is_empty() {
+            let line = text[..text.len() - rest.len()].matches('\n').count() + 1;
+            let error = |msg: &str| mlua::Error::external(format!("{name}:{line}: {msg}"));
+
+            let (command, after) = control_sequence(rest).ok_or_else(|| {
+                error("Expected `\\newcommand`, `\\def` or `\\DeclareMathOperator`")
+            })?;
+            let after = after.strip_prefix('*').unwrap_or(after);
+
+            let (key, definition, after) = match command {
+                "newcommand" | "renewcommand" | "providecommand" => {
+                    let (key, after) = argument(after)
+                        .and_then(|(x, after)| Some((control_sequence(x.trim())?.0, after)))
+                        .ok_or_else(|| error("Expected the name of the macro, like `\\R`"))?;
+
+                    // number of arguments, and the default for the first one
+                    let (args, after) = match optional(after) {
+                        Some((args, after)) => (
+                            args.trim()
+                                .parse::<usize>()
+                                .ok()
+                                .filter(|x| *x <= 9)
+                                .ok_or_else(|| {
+                                    error(&format!("Expected 0 to 9 arguments, found `{args}`"))
+                                })?,
+                            after,
+                        ),
+                        None => (0, after),
+                    };
+                    let (default, after) = match optional(after) {
+                        Some((default, after)) => (Some(default.to_string()), after),
+                        None => (None, after),
+                    };
+
+                    let (body, after) = argument(after)
+                        .ok_or_else(|| error(&format!("Expected the body of `\\{key}`")))?;
+                    let definition = Macro {
+                        args,
+                        default,
+                        body: body.to_string(),
+                    };
+                    (key, definition, after)
+                }
+                "def" => {
+                    let (key, after) = control_sequence(after.trim_start())
+                        .ok_or_else(|| error("Expected the name of the macro, like `\\R`"))?;
+
+                    // parameters, like `#1#2`
+                    let (params, after) = after.split_at(after.find('{').unwrap_or(after.len()));
+                    let (body, after) = argument(after)
+                        .ok_or_else(|| error(&format!("Expected the body of `\\{key}`")))?;
+                    let definition = Macro {
+                        args: params.matches('#').count(),
+                        default: None,
+                        body: body.to_string(),
+                    };
+                    (key, definition, after)
+                }
+                "DeclareMathOperator" => {
+                    let (key, after) = argument(after)
+                        .and_then(|(x, after)| Some((control_sequence(x.trim())?.0, after)))
+                        .ok_or_else(|| error("Expected the name of the operator, like `\\tr`"))?;
+                    let (body, after) = argument(after)
+                        .ok_or_else(|| error(&format!("Expected the body of `\\{key}`")))?;
+                    let definition = Macro {
+                        args: 0,
+                        default: None,
+                        body: format!("\\operatorname{{{body}}}"),
+                    };
+                    (key, definition, after)
+                }
+                command => {
+                    return Err(error(&format!(
+                        "Expected `\\newcommand`, `\\def` or `\\DeclareMathOperator`, found `\\{command}`"
+                    )));
+                }
+            };
+
+            macros.insert(key.to_string(), definition);
+            rest = after.trim_start();
+        }
+
+        Ok(Self(macros))
+    }
+
+    /// Expand all macros in the tex
+    pub(crate) fn expand(&self, tex: &str) -> Result<String> {
+        if self.0.is_empty() {
+            return Ok(tex.to_string());
+        }
+
+        // expand until there is nothing left to expand
+        let limit = tex.len().max(1024) * MAX_GROWTH;
+        let mut tex = tex.to_string();
+        for _ in 0..MAX_DEPTH {
+            match self.expand_once(&tex, limit)? {
+                Some(expanded) => tex = expanded,
+                None => return Ok(tex),
+            }
+        }
+
+        Err(mlua::Error::external(
+            "Math macros expand too deep, is one of them recursive?",
+        ))
+    }
+
+    /// Expand the macros in the tex, but not the ones they expand to
+    /// Fails if the result gets longer than the limit
+    fn expand_once(&self, tex: &str, limit: usize) -> Result<Option<String>> {
+        let mut out = String::with_capacity(tex.len());
+        let mut rest = tex;
+        let mut expanded = false;
+        while let Some(start) = rest.find('\\') {
+            if out.len() > limit {
+                return Err(mlua::Error::external(
+                    "Math macros expand to too much tex, is one of them recursive?",
+                ));
+            }
+
+            join(&mut out, &rest[..start]);
+            rest = &rest[start..];
+
+            // trailing backslash
+            let Some((name, after)) = control_sequence(rest) else {
+                break;
+            };
+
+            // not a macro, keep it
+            let Some(definition) = self.0.get(name) else {
+                join(&mut out, &rest[..rest.len() - after.len()]);
+                rest = after;
+                continue;
+            };
+
+            // arguments, the first one can be optional
+            let mut args = Vec::new();
+            let mut after = after;
+            if let Some(default) = &definition.default {
+                match optional(after) {
+                    Some((arg, x)) => {
+                        args.push(arg);
+                        after = x;
+                    }
+                    None => args.push(default),
+                }
+            }
+            while args.len() < definition.args {
+                let (arg, x) = argument(after).ok_or_else(|| {
+                    mlua::Error::external(format!("Math macro `\\{name}` is missing an argument"))
+                })?;
+                args.push(arg);
+                after = x;
+            }
+
+            // fill in the arguments
+            let mut body = definition.body.as_str();
+            while let Some(index) = body.find('#') {
+                join(&mut out, &body[..index]);
+                let param = body[index + 1..].chars().next();
+                match param {
+                    Some('#') => out.push('#'),
+                    Some(x @ '1'..='9') => join(
+                        &mut out,
+                        args.get(x as usize - '1' as usize)
+                            .copied()
+                            .unwrap_or_default(),
+                    ),
+                    _ => out.push('#'),
+                }
+                body = &body[index + 1 + param.map(char::len_utf8).unwrap_or(0)..];
+            }
+            join(&mut out, body);
+
+            rest = after;
+            expanded = true;
+        }
+
+        join(&mut out, rest);
+        Ok(expanded.then_some(out))
+    }
+}
+
+/// Take the `\label{...}` out of display math, giving the label and the math without it
+pub(crate) fn take_label(tex: &str) -> (Option<&str>, String) {
+    let mut rest = tex;
+    while let Some(start) = rest.find('\\') {
+        let Some((name, after)) = control_sequence(&rest[start..]) else {
+            break;
+        };
+        if name == "label"
+            && let Some((label, after)) = argument(after)
+        {
+            let before = &tex[..tex.len() - rest.len() + start];
+            return (Some(label.trim()), format!("{before}{after}"));
+        }
+        rest = after;
+    }
+
+    (None, tex.to_string())
+}
+
+/// Reference to an equation in text, like `\eqref{eq:x}` or `\ref{eq:x}`
+pub(crate) struct Reference<'a> {
+    /// Where the reference starts in the text
+    pub start: usize,
+
+    /// Where the reference ends in the text
+    pub end: usize,
+
+    /// Label of the equation
+    pub label: &'a str,
+
+    /// Whether the number goes in parentheses, as with `\eqref`
+    pub parens: bool,
+}
+
+/// Find the references to equations in text
+pub(crate) fn references(text: &str) -> Vec<Reference<'_>> {
+    let mut references = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('\\') {
+        let start = text.len() - rest.len() + start;
+        let Some((name, after)) = control_sequence(&text[start..]) else {
+            break;
+        };
+        rest = after;
+
+        // only references with a label in braces
+        if (name == "eqref" || name == "ref")
+            && after.starts_with('{')
+            && let Some((label, after)) = argument(after)
+        {
+            references.push(Reference {
+                start,
+                end: text.len() - after.len(),
+                label: label.trim(),
+                parens: name == "eqref",
+            });
+            rest = after;
+        }
+    }
+
+    references
+}
+
+/// Add text, with a space between a command and the letters after it, so `\alpha` and `x`
+/// don't become `\alphax`
+fn join(out: &mut String, text: &str) {
+    let word = out.trim_end_matches(|x: char| x.is_ascii_alphabetic());
+    if word.len() < out.len()
+        && word.ends_with('\\')
+        && !escaped(&word[..word.len() - 1])
+        && text.starts_with(|x: char| x.is_ascii_alphabetic())
+    {
+        out.push(' ');
+    }
+    out.push_str(text);
+}
+
+/// Whether a character after the text is escaped by a backslash
+fn escaped(text: &str) -> bool {
+    (text.len() - text.trim_end_matches('\\').len()) % 2 == 1
+}
+
+/// Name of the command at the start of the text, and the text after it
+fn control_sequence(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix('\\')?;
+    let letters = text
+        .find(|x: char| !x.is_ascii_alphabetic())
+        .unwrap_or(text.len());
+    let end = match letters {
+        0 => text.chars().next()?.len_utf8(),
+        letters => letters,
+    };
+    Some(text.split_at(end))
+}
+
+/// Argument at the start of the text, either a group in braces, a command or a character
+fn argument(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if text.starts_with('{') {
+        let end = closing(text, '{', '}')?;
+        Some((&text[1..end], &text[end + 1..]))
+    } else if text.starts_with('\\') {
+        let (name, after) = control_sequence(text)?;
+        Some((&text[..name.len() + 1], after))
+    } else {
+        let len = text.chars().next()?.len_utf8();
+        Some(text.split_at(len))
+    }
+}
+
+/// Optional argument in brackets at the start of the text
+fn optional(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if !text.starts_with('[') {
+        return None;
+    }
+    let end = closing(text, '[', ']')?;
+    Some((&text[1..end], &text[end + 1..]))
+}
+
+/// Index of the closing bracket for the opening one at the start of the text
+/// Brackets in braces or escaped by a backslash are skipped
+fn closing(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut braces = 0;
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '{' if open != '{' => braces += 1,
+            '}' if open != '{' => braces -= 1,
+            x if x == open && braces == 0 => depth += 1,
+            x if x == close && braces == 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREAMBLE: &str = r"
+        % number sets
+        \newcommand{\R}{\mathbb{R}}
+        \renewcommand*\abs[1]{\left|#1\right|} % with an argument
+        \newcommand{\norm}[2][2]{\|#2\|_{#1}}
+        \def\pair#1#2{(#1, #2)}
+        \DeclareMathOperator{\tr}{tr}
+        \newcommand{\percent}{50\%}
+    ";
+
+    #[test]
+    fn expand() {
+        let macros = Macros::parse(PREAMBLE, "macros.tex").unwrap();
+        assert_eq!(macros.expand(r"x \in \R").unwrap(), r"x \in \mathbb{R}");
+        assert_eq!(
+            macros.expand(r"\abs{x}+\abs y").unwrap(),
+            r"\left|x\right|+\left|y\right|"
+        );
+        assert_eq!(macros.expand(r"\norm{v}").unwrap(), r"\|v\|_{2}");
+        assert_eq!(
+            macros.expand(r"\norm[\infty]{v}").unwrap(),
+            r"\|v\|_{\infty}"
+        );
+        assert_eq!(macros.expand(r"\pair{a}{\R}").unwrap(), r"(a, \mathbb{R})");
+        assert_eq!(macros.expand(r"\tr A").unwrap(), r"\operatorname{tr} A");
+        assert_eq!(macros.expand(r"\percent").unwrap(), r"50\%");
+
+        // commands and letters stay apart
+        assert_eq!(
+            macros.expand(r"\abs\alpha").unwrap(),
+            r"\left|\alpha\right|"
+        );
+        assert_eq!(macros.expand(r"\R x").unwrap(), r"\mathbb{R} x");
+
+        // unknown commands and escaped backslashes stay
+        assert_eq!(macros.expand(r"\alpha\\\R").unwrap(), r"\alpha\\\mathbb{R}");
+    }
+
+    #[test]
+    fn nested() {
+        let macros = Macros::parse(r"\newcommand\a[1]{\b{#1}\b#1}\newcommand\b[1]{[#1]}", "m");
+        assert_eq!(macros.unwrap().expand(r"\a{x}").unwrap(), "[x][x]");
+    }
+
+    #[test]
+    fn errors() {
+        let error = Macros::parse("\\newcommand{\\a}{a}\n\\foo", "macros.tex")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("macros.tex:2: Expected"));
+        assert!(Macros::parse(r"\newcommand{\a}[10]{a}", "m").is_err());
+        assert!(Macros::parse(r"\newcommand{\a}", "m").is_err());
+        assert!(Macros::parse(r"\newcommand{\a}{a", "m").is_err());
+
+        let macros = Macros::parse(r"\newcommand{\a}[2]{#1#2}", "m").unwrap();
+        assert!(macros.expand(r"\a{x}").is_err());
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            take_label(r"x^2 \label{ eq:square } + 1"),
+            (Some("eq:square"), r"x^2  + 1".to_string())
+        );
+        assert_eq!(
+            take_label(r"x \labels{a}"),
+            (None, r"x \labels{a}".to_string())
+        );
+        assert_eq!(take_label(r"x \\ y"), (None, r"x \\ y".to_string()));
+    }
+
+    #[test]
+    fn find_references() {
+        let text = r"see \eqref{eq:a} and \ref{ b }, not \ref b or \refs{c}";
+        let found = references(text);
+        assert_eq!(found.len(), 2);
+        assert_eq!(&text[found[0].start..found[0].end], r"\eqref{eq:a}");
+        assert_eq!((found[0].label, found[0].parens), ("eq:a", true));
+        assert_eq!(&text[found[1].start..found[1].end], r"\ref{ b }");
+        assert_eq!((found[1].label, found[1].parens), ("b", false));
+    }
+
+    #[test]
+    fn recursive() {
+        let macros = Macros::parse(r"\def\a{\a}", "m").unwrap();
+        assert!(macros.expand(r"\a").is_err());
+
+        // grows exponentially, and is stopped before running out of memory
+        let macros = Macros::parse(r"\def\a{\a\a}", "m").unwrap();
+        let error = macros.expand(r"\a").err().unwrap().to_string();
+        assert!(error.contains("too much tex"), "{error}");
+    }
+}
@@ -1,5 +1,7 @@
 use relative_path::{RelativePath, RelativePathBuf};
 
+use crate::date::{parse_ymd, today};
+
 pub(crate) trait DoubleFileExt {
     fn has_double_ext(&self, ext: &str) -> bool;
     fn without_double_ext(&self) -> Option<RelativePathBuf>;
@@ -70,3 +72,75 @@ impl<T: AsRef<RelativePath>> HtmlToIndex for T {
         }
     }
 }
+
+pub(crate) trait Draft {
+    fn is_draft(&self) -> bool;
+    fn publish_date(&self) -> Option<(i64, u32, u32)>;
+    fn is_published(&self, scheduled: bool) -> bool;
+    fn without_draft(&self) -> RelativePathBuf;
+}
+
+impl<T: AsRef<RelativePath>> Draft for T {
+    fn is_draft(&self) -> bool {
+        // any part of the path can have a .draft extension
+        self.as_ref()
+            .components()
+            .any(|x| x.as_str().split('.').skip(1).any(|x| x == "draft"))
+    }
+
+    fn publish_date(&self) -> Option<(i64, u32, u32)> {
+        // latest YYYY-MM-DD- prefix in any of the parts of the path
+        self.as_ref()
+            .components()
+            .filter(|x| x.as_str().as_bytes().get(10) == Some(&b'-'))
+            .filter_map(|x| parse_ymd(x.as_str()))
+            .max()
+    }
+
+    fn is_published(&self, scheduled: bool) -> bool {
+        // dates only count if scheduling is turned on
+        !self.is_draft()
+            && (!scheduled || self.publish_date().map(|x| x <= today()).unwrap_or(true))
+    }
+
+    fn without_draft(&self) -> RelativePathBuf {
+        // remove the .draft extension from all parts
+        RelativePathBuf::from_iter(self.as_ref().components().map(|x| {
+            let mut parts = x.as_str().split('.');
+            let stem = parts.next().unwrap_or("");
+            parts
+                .filter(|x| *x != "draft")
+                .fold(stem.to_string(), |acc, x| acc + "." + x)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drafts() {
+        assert!(RelativePath::new("blog/post.draft.md").is_draft());
+        assert!(RelativePath::new("blog.draft/post.md").is_draft());
+        assert!(!RelativePath::new("blog/draft.md").is_draft());
+        assert_eq!(
+            RelativePath::new("blog.draft/post.draft.lua.md").without_draft(),
+            "blog/post.lua.md"
+        );
+    }
+
+    #[test]
+    fn scheduled() {
+        let future = RelativePath::new("events/9999-01-01-party.md");
+        let past = RelativePath::new("2000-01-01-blog/post.md");
+        assert_eq!(future.publish_date(), Some((9999, 1, 1)));
+        assert_eq!(past.publish_date(), Some((2000, 1, 1)));
+
+        // only scheduled when turned on
+        assert!(future.is_published(false));
+        assert!(!future.is_published(true));
+        assert!(past.is_published(true));
+        assert!(!RelativePath::new("post.draft.md").is_published(false));
+    }
+}
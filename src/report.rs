@@ -0,0 +1,206 @@
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+use relative_path::RelativePathBuf;
+
+use crate::json::escape_json;
+
+/// What produced an output file
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Pipeline {
+    Markdown,
+    Template,
+    Sass,
+    Subset,
+    Copy,
+    EmitFile,
+    Asset,
+}
+
+impl Pipeline {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Markdown => "markdown",
+            Self::Template => "template",
+            Self::Sass => "sass",
+            Self::Subset => "subset",
+            Self::Copy => "copy",
+            Self::EmitFile => "emitfile",
+            Self::Asset => "asset",
+        }
+    }
+}
+
+/// Information about how the site was built
+#[derive(Default)]
+pub(crate) struct Report {
+    /// Where each output file came from, and how it was made
+    pub outputs: BTreeMap<RelativePathBuf, (Option<RelativePathBuf>, Pipeline)>,
+
+    /// How long each stage took, in order
+    pub stages: Vec<(&'static str, Duration)>,
+
+    /// How many times a cache was used instead of doing the work again
+    pub cache_hits: BTreeMap<&'static str, usize>,
+
+    /// Warnings emitted while building
+    pub warnings: Vec<String>,
+}
+
+impl Report {
+    /// Record where an output file came from
+    pub(crate) fn output(
+        &mut self,
+        path: RelativePathBuf,
+        source: Option<RelativePathBuf>,
+        pipeline: Pipeline,
+    ) {
+        self.outputs.insert(path, (source, pipeline));
+    }
+
+    /// Record how long a stage took
+    pub(crate) fn stage(&mut self, name: &'static str, duration: Duration) {
+        self.stages.push((name, duration));
+    }
+
+    /// Record a cache hit
+    pub(crate) fn cache_hit(&mut self, cache: &'static str) {
+        *self.cache_hits.entry(cache).or_default() += 1;
+    }
+
+    /// Turn the report into json, using the given files for their sizes
+    pub(crate) fn to_json(&self, files: &BTreeMap<RelativePathBuf, Vec<u8>>) -> String {
+        let mut out = String::from("{\n  \"files\": [");
+
+        // all output files
+        for (i, (path, content)) in files.iter().enumerate() {
+            let (source, pipeline) = self
+                .outputs
+                .get(path)
+                .map(|(s, p)| (s.as_ref(), p.name()))
+                .unwrap_or((None, "unknown"));
+
+            write!(
+                out,
+                "{}\n    {{ \"path\": {}, \"size\": {}, \"source\": {}, \"pipeline\": {} }}",
+                if i > 0 { "," } else { "" },
+                escape_json(path.as_str()),
+                content.len(),
+                source
+                    .map(|x| escape_json(x.as_str()))
+                    .unwrap_or("null".to_string()),
+                escape_json(pipeline),
+            )
+            .expect("Writing to a string does not fail");
+        }
+        out.push_str("\n  ],\n  \"stages\": [");
+
+        // timings
+        for (i, (name, duration)) in self.stages.iter().enumerate() {
+            write!(
+                out,
+                "{}\n    {{ \"stage\": {}, \"ms\": {:.3} }}",
+                if i > 0 { "," } else { "" },
+                escape_json(name),
+                duration.as_secs_f64() * 1000.0,
+            )
+            .expect("Writing to a string does not fail");
+        }
+
+        // total time
+        write!(
+            out,
+            "\n  ],\n  \"total_ms\": {:.3},\n  \"cache_hits\": {{",
+            self.stages
+                .iter()
+                .map(|x| x.1)
+                .sum::<Duration>()
+                .as_secs_f64()
+                * 1000.0
+        )
+        .expect("Writing to a string does not fail");
+
+        // cache hits
+        for (i, (name, hits)) in self.cache_hits.iter().enumerate() {
+            write!(
+                out,
+                "{}\n    {}: {}",
+                if i > 0 { "," } else { "" },
+                escape_json(name),
+                hits
+            )
+            .expect("Writing to a string does not fail");
+        }
+        out.push_str("\n  },\n  \"warnings\": [");
+
+        // warnings
+        for (i, warning) in self.warnings.iter().enumerate() {
+            write!(
+                out,
+                "{}\n    {}",
+                if i > 0 { "," } else { "" },
+                escape_json(warning)
+            )
+            .expect("Writing to a string does not fail");
+        }
+        out.push_str("\n  ]\n}\n");
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let mut report = Report::default();
+        report.output(
+            "index.html".into(),
+            Some("index.lua.md".into()),
+            Pipeline::Markdown,
+        );
+        report.output("feed.xml".into(), None, Pipeline::EmitFile);
+        report.stage("setup", Duration::from_micros(1500));
+        report.stage("process", Duration::from_millis(2));
+        report.cache_hit("sass");
+        report.cache_hit("sass");
+        report.warnings.push("a \"warning\"".to_string());
+
+        let files = BTreeMap::from([
+            ("feed.xml".into(), b"<rss/>".to_vec()),
+            ("index.html".into(), b"<p>hi</p>".to_vec()),
+            ("other.txt".into(), Vec::new()),
+        ]);
+        assert_eq!(
+            report.to_json(&files),
+            r#"{
+  "files": [
+    { "path": "feed.xml", "size": 6, "source": null, "pipeline": "emitfile" },
+    { "path": "index.html", "size": 9, "source": "index.lua.md", "pipeline": "markdown" },
+    { "path": "other.txt", "size": 0, "source": null, "pipeline": "unknown" }
+  ],
+  "stages": [
+    { "stage": "setup", "ms": 1.500 },
+    { "stage": "process", "ms": 2.000 }
+  ],
+  "total_ms": 3.500,
+  "cache_hits": {
+    "sass": 2
+  },
+  "warnings": [
+    "a \"warning\""
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            Report::default().to_json(&BTreeMap::new()),
+            "{\n  \"files\": [\n  ],\n  \"stages\": [\n  ],\n  \"total_ms\": 0.000,\n  \"cache_hits\": {\n  },\n  \"warnings\": [\n  ]\n}\n"
+        );
+    }
+}
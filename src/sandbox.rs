@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use mlua::{Lua, LuaOptions, Result, StdLib, chunk};
+use relative_path::{Component, RelativePathBuf};
+
+/// Create the lua state
+/// In safe mode, scripts can't touch anything outside the site, or run other programs
+pub(crate) fn new_lua(safe: bool) -> Result<Lua> {
+    if !safe {
+        return Ok(unsafe { Lua::unsafe_new() });
+    }
+
+    // no ffi, but fennel needs part of debug
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL_SAFE | StdLib::DEBUG, LuaOptions::new()) };
+
+    let check = lua.create_function(|_, path: String| Ok(site_file(&path, true)?.to_string()))?;
+    lua.load(chunk! {
+        local open, lines, load, loadfile = io.open, io.lines, load, loadfile
+        local check = $check
+
+        // files can only be read
+        local function confined(path, mode)
+            mode = mode or "r"
+            if mode ~= "r" and mode ~= "rb" then
+                error("Can't open `" .. tostring(path) .. "` for writing in safe mode", 2)
+            end
+            return open(check(path), mode)
+        end
+
+        io = {
+            open = confined,
+            lines = function(path, ...)
+                if path == nil then return lines() end
+                return lines(check(path), ...)
+            end,
+            read = io.read,
+            write = io.write,
+            flush = io.flush,
+            type = io.type,
+            stdin = io.stdin,
+            stdout = io.stdout,
+            stderr = io.stderr,
+        }
+        os = { clock = os.clock, date = os.date, time = os.time, difftime = os.difftime }
+        debug = { getinfo = debug.getinfo, traceback = debug.traceback }
+
+        // only load source code, as bytecode can escape the sandbox
+        _G.load = function(chunk, name, _, env) return load(chunk, name, "t", env) end
+        _G.loadstring = function(chunk, name) return load(chunk, name, "t") end
+        _G.loadfile = function(path, _, env) return loadfile(check(path), "t", env) end
+        _G.dofile = function(path) return assert(loadfile(check(path), "t"))() end
+
+        // no c modules
+        package.loadlib = nil
+        package.cpath = ""
+        local loaders = package.loaders or package.searchers
+        table.remove(loaders, 4)
+        table.remove(loaders, 3)
+
+        // lua modules are checked like any other file
+        loaders[2] = function(name)
+            local path, err = package.searchpath(name, package.path)
+            if not path then return err end
+            return assert(_G.loadfile(path))
+        end
+
+        package.loaded.io = io
+        package.loaded.os = os
+        package.loaded.debug = debug
+        package.loaded.ffi = nil
+        package.preload.ffi = nil
+    })
+    .exec()?;
+
+    Ok(lua)
+}
+
+/// Path to a file in the site
+/// In safe mode, the path can't lead outside the site, also not through symlinks
+pub(crate) fn site_file(path: &str, safe: bool) -> Result<RelativePathBuf> {
+    let relative = RelativePathBuf::from(path);
+    if safe
+        && (Path::new(path).has_root()
+            || relative.normalize().components().next() == Some(Component::ParentDir)
+            || !inside_site(&relative.to_path(".")))
+    {
+        return Err(mlua::Error::external(format!(
+            "Can't access `{path}` in safe mode, as it's outside the site"
+        )));
+    }
+
+    Ok(relative)
+}
+
+/// Whether the path is inside the site once symlinks are resolved
+/// Paths that don't exist are checked by the closest parent that does
+fn inside_site(path: &Path) -> bool {
+    let Ok(root) = Path::new(".").canonicalize() else {
+        return false;
+    };
+    path.ancestors()
+        .find_map(|x| x.canonicalize().ok())
+        .is_some_and(|x| x.starts_with(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outside_site() {
+        assert!(site_file("src/main.rs", true).is_ok());
+        assert!(site_file("src/missing/file.txt", true).is_ok());
+        assert!(site_file("src/../Cargo.toml", true).is_ok());
+        assert!(site_file("../file.txt", true).is_err());
+        assert!(site_file("src/../../file.txt", true).is_err());
+        assert!(site_file("/etc/passwd", true).is_err());
+        assert!(site_file("../file.txt", false).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_outside_site() {
+        let link = ".symlink-outside-site";
+        let _ = std::fs::remove_file(link);
+        std::os::unix::fs::symlink("/", link).unwrap();
+        let file = site_file(&format!("{link}/etc/passwd"), true);
+        let missing = site_file(&format!("{link}/missing"), true);
+        let unsafe_file = site_file(&format!("{link}/etc/passwd"), false);
+        std::fs::remove_file(link).unwrap();
+
+        assert!(file.is_err());
+        assert!(missing.is_err());
+        assert!(unsafe_file.is_ok());
+    }
+}
@@ -0,0 +1,424 @@
+use mlua::Result;
+
+use crate::dom::Dom;
+
+/// Css selector, a list of complex selectors separated by commas
+pub(crate) struct Selector(Vec<Vec<(Combinator, Compound)>>);
+
+/// How a compound selector relates to the one before it
+#[derive(Clone, Copy, PartialEq)]
+enum Combinator {
+    /// `a b`
+    Descendant,
+
+    /// `a > b`
+    Child,
+
+    /// `a + b`
+    Adjacent,
+
+    /// `a ~ b`
+    Sibling,
+}
+
+/// Selectors that apply to a single element, like `a.link[href]`
+#[derive(Default)]
+struct Compound {
+    tag: Option<String>,
+    id: Vec<String>,
+    classes: Vec<String>,
+    attrs: Vec<(String, Option<(AttrOp, String)>)>,
+    pseudo: Vec<Pseudo>,
+}
+
+/// How to compare an attribute
+#[derive(Clone, Copy)]
+enum AttrOp {
+    /// `[a=b]`
+    Equals,
+
+    /// `[a~=b]`, one of the words
+    Includes,
+
+    /// `[a|=b]`, equal or followed by `-`
+    Dash,
+
+    /// `[a^=b]`
+    Prefix,
+
+    /// `[a$=b]`
+    Suffix,
+
+    /// `[a*=b]`
+    Contains,
+}
+
+enum Pseudo {
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    Empty,
+    NthChild(usize),
+    Not(Selector),
+}
+
+impl Selector {
+    /// Parse a selector
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser { text, pos: 0 };
+        let selector = parser
+            .selector()
+            .map_err(|e| mlua::Error::external(format!("Invalid selector `{text}`: {e}")))?;
+        if parser.pos < text.len() {
+            return Err(mlua::Error::external(format!(
+                "Invalid selector `{text}`: unexpected `{}`",
+                &text[parser.pos..]
+            )));
+        }
+        Ok(selector)
+    }
+
+    /// Whether the element matches the selector
+    pub(crate) fn matches(&self, dom: &Dom, id: usize) -> bool {
+        self.0
+            .iter()
+            .any(|complex| matches_complex(dom, id, complex))
+    }
+}
+
+/// Match a complex selector, from right to left
+fn matches_complex(dom: &Dom, id: usize, parts: &[(Combinator, Compound)]) -> bool {
+    let Some(((combinator, compound), rest)) = parts.split_last() else {
+        return true;
+    };
+    if !compound.matches(dom, id) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+
+    match combinator {
+        Combinator::Child => dom
+            .parent_element(id)
+            .is_some_and(|x| matches_complex(dom, x, rest)),
+        Combinator::Descendant => {
+            let mut parent = dom.parent_element(id);
+            while let Some(x) = parent {
+                if matches_complex(dom, x, rest) {
+                    return true;
+                }
+                parent = dom.parent_element(x);
+            }
+            false
+        }
+        Combinator::Adjacent => dom
+            .previous_element(id)
+            .is_some_and(|x| matches_complex(dom, x, rest)),
+        Combinator::Sibling => {
+            let mut previous = dom.previous_element(id);
+            while let Some(x) = previous {
+                if matches_complex(dom, x, rest) {
+                    return true;
+                }
+                previous = dom.previous_element(x);
+            }
+            false
+        }
+    }
+}
+
+impl Compound {
+    fn matches(&self, dom: &Dom, id: usize) -> bool {
+        let Some(tag) = dom.tag(id) else {
+            return false;
+        };
+
+        // tag
+        if let Some(expected) = &self.tag
+            && !expected.eq_ignore_ascii_case(tag)
+        {
+            return false;
+        }
+
+        // id
+        if !self.id.iter().all(|x| dom.attr(id, "id") == Some(x)) {
+            return false;
+        }
+
+        // classes
+        let classes = dom.attr(id, "class").unwrap_or("");
+        if !self
+            .classes
+            .iter()
+            .all(|x| classes.split_ascii_whitespace().any(|y| x == y))
+        {
+            return false;
+        }
+
+        // attributes
+        for (name, op) in &self.attrs {
+            let Some(value) = dom.attr(id, name) else {
+                return false;
+            };
+            let matched = match op {
+                None => true,
+                Some((AttrOp::Equals, x)) => value == x,
+                Some((AttrOp::Includes, x)) => value.split_ascii_whitespace().any(|y| x == y),
+                Some((AttrOp::Dash, x)) => {
+                    value == x
+                        || value
+                            .strip_prefix(x.as_str())
+                            .is_some_and(|y| y.starts_with('-'))
+                }
+                Some((AttrOp::Prefix, x)) => !x.is_empty() && value.starts_with(x.as_str()),
+                Some((AttrOp::Suffix, x)) => !x.is_empty() && value.ends_with(x.as_str()),
+                Some((AttrOp::Contains, x)) => !x.is_empty() && value.contains(x.as_str()),
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        // pseudo classes
+        self.pseudo.iter().all(|x| match x {
+            Pseudo::FirstChild => dom.previous_element(id).is_none(),
+            Pseudo::LastChild => dom.next_element(id).is_none(),
+            Pseudo::OnlyChild => {
+                dom.previous_element(id).is_none() && dom.next_element(id).is_none()
+            }
+            Pseudo::Empty => dom.is_empty(id),
+            Pseudo::NthChild(n) => {
+                let mut index = 1;
+                let mut previous = dom.previous_element(id);
+                while let Some(x) = previous {
+                    index += 1;
+                    previous = dom.previous_element(x);
+                }
+                index == *n
+            }
+            Pseudo::Not(selector) => !selector.matches(dom, id),
+        })
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while let Some(c) = self.peek().filter(|x| x.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+        self.pos > start
+    }
+
+    fn selector(&mut self) -> std::result::Result<Selector, String> {
+        let mut list = vec![self.complex()?];
+        while self.peek() == Some(',') {
+            self.pos += 1;
+            list.push(self.complex()?);
+        }
+        Ok(Selector(list))
+    }
+
+    fn complex(&mut self) -> std::result::Result<Vec<(Combinator, Compound)>, String> {
+        let mut parts = Vec::new();
+        let mut combinator = Combinator::Descendant;
+        self.whitespace();
+
+        loop {
+            parts.push((combinator, self.compound()?));
+
+            // combinator, or end
+            let space = self.whitespace();
+            combinator = match self.peek() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::Adjacent,
+                Some('~') => Combinator::Sibling,
+                Some(',' | ')') | None => return Ok(parts),
+                Some(_) if space => Combinator::Descendant,
+                Some(c) => return Err(format!("unexpected `{c}`")),
+            };
+            if combinator != Combinator::Descendant {
+                self.pos += 1;
+                self.whitespace();
+            }
+        }
+    }
+
+    fn compound(&mut self) -> std::result::Result<Compound, String> {
+        let mut compound = Compound::default();
+        let start = self.pos;
+
+        // tag
+        if self.peek() == Some('*') {
+            self.pos += 1;
+        } else if let Some(tag) = self.ident() {
+            compound.tag = Some(tag);
+        }
+
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.pos += 1;
+                    compound.id.push(self.ident().ok_or("expected an id")?);
+                }
+                Some('.') => {
+                    self.pos += 1;
+                    compound
+                        .classes
+                        .push(self.ident().ok_or("expected a class")?);
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    compound.attrs.push(self.attr()?);
+                }
+                Some(':') => {
+                    self.pos += 1;
+                    compound.pseudo.push(self.pseudo()?);
+                }
+                _ if self.pos == start => return Err("expected a selector".to_string()),
+                _ => return Ok(compound),
+            }
+        }
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        let start = self.pos;
+        while let Some(c) = self
+            .peek()
+            .filter(|x| x.is_alphanumeric() || *x == '-' || *x == '_')
+        {
+            self.pos += c.len_utf8();
+        }
+        Some(self.text[start..self.pos].to_string()).filter(|x| !x.is_empty())
+    }
+
+    fn attr(&mut self) -> std::result::Result<(String, Option<(AttrOp, String)>), String> {
+        self.whitespace();
+        let name = self.ident().ok_or("expected an attribute name")?;
+        self.whitespace();
+
+        // operator
+        let rest = &self.text[self.pos..];
+        let op = [
+            ("=", AttrOp::Equals),
+            ("~=", AttrOp::Includes),
+            ("|=", AttrOp::Dash),
+            ("^=", AttrOp::Prefix),
+            ("$=", AttrOp::Suffix),
+            ("*=", AttrOp::Contains),
+        ]
+        .into_iter()
+        .find(|x| rest.starts_with(x.0));
+
+        let op = if let Some((text, op)) = op {
+            self.pos += text.len();
+            self.whitespace();
+
+            // quoted or unquoted value
+            let value = match self.peek() {
+                Some(quote @ ('"' | '\'')) => {
+                    let end = self.text[self.pos + 1..]
+                        .find(quote)
+                        .ok_or("unterminated string")?;
+                    let value = self.text[self.pos + 1..self.pos + 1 + end].to_string();
+                    self.pos += end + 2;
+                    value
+                }
+                _ => self.ident().ok_or("expected an attribute value")?,
+            };
+            self.whitespace();
+            Some((op, value))
+        } else {
+            None
+        };
+
+        if self.peek() != Some(']') {
+            return Err("expected `]`".to_string());
+        }
+        self.pos += 1;
+
+        Ok((name.to_ascii_lowercase(), op))
+    }
+
+    fn pseudo(&mut self) -> std::result::Result<Pseudo, String> {
+        let name = self.ident().ok_or("expected a pseudo class")?;
+        match name.as_str() {
+            "first-child" => Ok(Pseudo::FirstChild),
+            "last-child" => Ok(Pseudo::LastChild),
+            "only-child" => Ok(Pseudo::OnlyChild),
+            "empty" => Ok(Pseudo::Empty),
+            "nth-child" | "not" => {
+                if self.peek() != Some('(') {
+                    return Err("expected `(`".to_string());
+                }
+                self.pos += 1;
+                self.whitespace();
+                let pseudo = if name == "not" {
+                    Pseudo::Not(self.selector()?)
+                } else {
+                    let n = self
+                        .ident()
+                        .and_then(|x| x.parse().ok())
+                        .ok_or("expected a number")?;
+                    Pseudo::NthChild(n)
+                };
+                self.whitespace();
+                if self.peek() != Some(')') {
+                    return Err("expected `)`".to_string());
+                }
+                self.pos += 1;
+                Ok(pseudo)
+            }
+            _ => Err(format!("unsupported pseudo class `:{name}`")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for selector in [
+            "div",
+            "*",
+            " div > p , a[href='x y'] ",
+            "ul li:nth-child(2) + li ~ li",
+            "a:not(.x, [rel|=no]):first-child",
+            "[data-x ^= a][b$=c][d*=\"e\"][f~=g]",
+        ] {
+            assert!(Selector::parse(selector).is_ok(), "{selector}");
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        for selector in [
+            "",
+            "div >",
+            "a,",
+            ".",
+            "#",
+            "[href",
+            "[href=]",
+            "[a='b]",
+            "p:hover",
+            ":not(a",
+            ":nth-child(x)",
+            "a ! b",
+        ] {
+            assert!(Selector::parse(selector).is_err(), "{selector}");
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     io::{BufRead, BufReader, Read, Write},
     net::{TcpListener, TcpStream},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         Arc,
         atomic::{AtomicBool, AtomicUsize, Ordering},
@@ -12,10 +12,11 @@ use std::{
 };
 
 use flate2::{Compression, read::GzEncoder};
-use notify::Watcher;
+use notify::{RecommendedWatcher, Watcher};
 use relative_path::RelativePathBuf;
 
 use crate::{
+    config::Flags,
     generate::{Site, generate},
     print::{html_error, print_error, print_success, print_warning},
 };
@@ -24,7 +25,7 @@ use mlua::{ErrorContext, ExternalResult};
 
 const VERY_LONG_PATH: &str = "very-long-path-name-intentionally-used-to-get-update-notifications-please-do-not-name-your-files-like-this.rs";
 
-pub(crate) fn serve(addr: &str) -> mlua::Result<()> {
+pub(crate) fn serve(addr: &str, flags: &Flags) -> mlua::Result<()> {
     // run the server
     let listener = TcpListener::bind(&addr)
         .unwrap_or_else(|e| panic!("Failed to serve site on {}: {}", addr, e));
@@ -33,7 +34,7 @@ pub(crate) fn serve(addr: &str) -> mlua::Result<()> {
     let start = Instant::now();
 
     // generate the initial site
-    let mut site = generate(true);
+    let mut site = generate(true, flags, None);
 
     // notify if it went bad
     if let Err(ref e) = site {
@@ -79,18 +80,10 @@ pub(crate) fn serve(addr: &str) -> mlua::Result<()> {
     let version_clone = version.clone();
 
     // watch for changes
-    let watcher =
-        // we only care about updates, so set the atomic to true if anything happened
-        notify::recommended_watcher(move |e: Result<notify::Event, notify::Error>|
-            // and make sure that said update is not just file access, otherwise we can trigger ourselves
-            if  e.map(|e| !e.kind.is_access()).unwrap_or(false) {
-                changed_clone.store(true, Ordering::Relaxed);
-                version_clone.fetch_add(1, Ordering::Relaxed);
-            })
-        // wrap the result ok with the watcher because we don't want it to drop out of scope
-        .and_then(|mut watcher| {
-            watcher.watch(&PathBuf::from("."), notify::RecursiveMode::Recursive).map(|_| watcher)
-        });
+    let watcher = watch_changes(None, move || {
+        changed_clone.store(true, Ordering::Relaxed);
+        version_clone.fetch_add(1, Ordering::Relaxed);
+    });
 
     // notify for an error, borrow to not drop it, as doing so would stop the watcher
     if let Err(e) = &watcher {
@@ -112,7 +105,7 @@ pub(crate) fn serve(addr: &str) -> mlua::Result<()> {
         let stream = incoming.recv_timeout(Duration::from_millis(100));
         match stream {
             Ok(s) => respond(s, &site, &version, &mut update_notify),
-            Err(_) => reload(&changed, &mut site, &version, &mut update_notify),
+            Err(_) => reload(&changed, &mut site, &version, &mut update_notify, flags),
         }
     }
 
@@ -122,6 +115,80 @@ pub(crate) fn serve(addr: &str) -> mlua::Result<()> {
     Ok(())
 }
 
+/// Watch the current directory for changes, and call the function when anything changed
+/// Changes that only happen inside the ignored directory are skipped
+pub(crate) fn watch_changes<F: Fn() + Send + 'static>(
+    ignore: Option<&Path>,
+    changed: F,
+) -> Result<RecommendedWatcher, notify::Error> {
+    // absolute paths, to compare against the ignored directory
+    let root = std::env::current_dir()
+        .and_then(|x| x.canonicalize())
+        .unwrap_or(PathBuf::from("."));
+    let ignore = ignore.map(Path::to_path_buf);
+
+    // we only care about updates, so call the function if anything happened
+    notify::recommended_watcher(move |e: Result<notify::Event, notify::Error>| {
+        // and make sure that said update is not just file access, otherwise we can trigger ourselves
+        let relevant = e
+            .map(|e| {
+                !e.kind.is_access()
+                    && !ignore
+                        .as_ref()
+                        .map(|x| e.paths.iter().all(|p| p.starts_with(x)))
+                        .unwrap_or(false)
+            })
+            .unwrap_or(false);
+
+        if relevant {
+            changed();
+        }
+    })
+    // wrap the result ok with the watcher because we don't want it to drop out of scope
+    .and_then(|mut watcher| {
+        watcher
+            .watch(&root, notify::RecursiveMode::Recursive)
+            .map(|_| watcher)
+    })
+}
+
+/// Report whether rebuilding the site went ok
+pub(crate) fn print_rebuilt(start: Instant, site: &mlua::Result<Site>) {
+    // notify if it went bad
+    if let Err(e) = site {
+        print_error("Failed to build site", e);
+    } else if let Ok(s) = site {
+        let count = s.files.len();
+        let size = s.files.values().map(|x| x.len()).sum::<usize>() as f64 / 1000.0;
+
+        // pick largest size to use for representation
+        // if bigger than one mb, scale down
+        let megabytes = if size > 1000.0 { true } else { false };
+        let size = if megabytes { size / 1000.0 } else { size };
+
+        // and if bigger than a gb, scale down
+        let gigabytes = if size > 1000.0 { true } else { false };
+        let size = if gigabytes { size / 1000.0 } else { size };
+
+        // pick unit
+        let unit = if gigabytes {
+            "gb"
+        } else if megabytes {
+            "mb"
+        } else {
+            "kb"
+        };
+
+        print_success(
+            &format!("Site rebuilt ({}ms)", start.elapsed().as_millis()),
+            &format!(
+                "{count} file{}, {size:.2}{unit} total",
+                if count > 1 { "s" } else { "" }
+            ),
+        );
+    }
+}
+
 fn respond(
     mut stream: TcpStream,
     site: &mlua::Result<Site>,
@@ -310,45 +377,15 @@ fn reload(
     site: &mut mlua::Result<Site>,
     version: &Arc<AtomicUsize>,
     update_notify: &mut Vec<TcpStream>,
+    flags: &Flags,
 ) {
     // went ok and there is no request, check if the site needs reloading
     if changed.swap(false, Ordering::Relaxed) {
         let start = Instant::now();
-        *site = generate(true);
+        *site = generate(true, flags, None);
 
         // notify if it went bad
-        if let Err(ref e) = *site {
-            print_error("Failed to build site", e);
-        } else if let Ok(ref s) = *site {
-            let count = s.files.len();
-            let size = s.files.values().map(|x| x.len()).sum::<usize>() as f64 / 1000.0;
-
-            // pick largest size to use for representation
-            // if bigger than one mb, scale down
-            let megabytes = if size > 1000.0 { true } else { false };
-            let size = if megabytes { size / 1000.0 } else { size };
-
-            // and if bigger than a gb, scale down
-            let gigabytes = if size > 1000.0 { true } else { false };
-            let size = if gigabytes { size / 1000.0 } else { size };
-
-            // pick unit
-            let unit = if gigabytes {
-                "gb"
-            } else if megabytes {
-                "mb"
-            } else {
-                "kb"
-            };
-
-            print_success(
-                &format!("Site rebuilt ({}ms)", start.elapsed().as_millis()),
-                &format!(
-                    "{count} file{}, {size:.2}{unit} total",
-                    if count > 1 { "s" } else { "" }
-                ),
-            );
-        }
+        print_rebuilt(start, site);
 
         // notify the listeners we got updated as well
         // only retain the ones that haven't errored out due to likely not being connected anymore
@@ -1,5 +1,6 @@
-use std::{collections::VecDeque, iter::repeat};
+use std::{cell::RefCell, collections::VecDeque, iter::repeat, rc::Rc};
 
+use glob::Pattern;
 use mlua::{
     Lua, Result,
     Value::{self, Nil},
@@ -8,19 +9,21 @@ use mlua::{
 use relative_path::RelativePath;
 use unicode_width::UnicodeWidthStr;
 
-use crate::path::{DoubleFileExt, HtmlToIndex};
+use crate::path::{DoubleFileExt, Draft, HtmlToIndex};
 
 pub(crate) fn template(
     lua: &Lua,
     content: &str,
     name: &RelativePath,
+    compile: bool,
 ) -> Result<(String, VecDeque<Value>)> {
     // translated name
     let path = name
         .without_double_ext()
         .ok_or(mlua::Error::external(format!(
             "Expected path `{name}` to have a second `.lua` or `.fnl` extension"
-        )))?;
+        )))?
+        .without_draft();
     let path = path.html_to_index().unwrap_or(path);
 
     // set up environment
@@ -38,6 +41,35 @@ pub(crate) fn template(
     lua.globals()
         .set("curtargetdir", path.parent().map(RelativePath::as_str))?;
 
+    // run the whole file as one function, or every block on it's own
+    let (out, functions) = if compile {
+        compiled(lua, content, name)?
+    } else {
+        blocks(lua, content, name)?
+    };
+
+    // unset environment
+    lua.globals().set("curfile", false)?;
+    lua.globals().set("curdir", false)?;
+    lua.globals().set("curtarget", false)?;
+    lua.globals().set("curtargetdir", false)?;
+
+    Ok((out, functions))
+}
+
+/// Whether to compile the file at path to one function, the last matching rule wins
+/// Files without a `.lua` or `.fnl` extension are never compiled
+pub(crate) fn compile_mode(rules: &[(Option<Pattern>, bool)], path: &RelativePath) -> bool {
+    (path.has_double_ext("lua") || path.has_double_ext("fnl"))
+        && rules
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.as_ref().is_none_or(|x| x.matches(path.as_str())))
+            .is_some_and(|(_, compile)| *compile)
+}
+
+/// Run every `<? ... ?>` block on it's own, and embed what it returns
+fn blocks(lua: &Lua, content: &str, name: &RelativePath) -> Result<(String, VecDeque<Value>)> {
     // output string
     let mut out = String::with_capacity(content.len());
 
@@ -77,18 +109,7 @@ pub(crate) fn template(
             // run code
             let result: Value = lua.load(code).set_name(format!("@{name}")).eval()?;
 
-            // string, numbers or booleans can be embedded directly
-            if result.is_string() || result.is_number() || result.is_integer() {
-                out.push_str(&lua.coerce_string(result)?.unwrap().to_str()?);
-            }
-            // boolean
-            else if let Some(b) = result.as_boolean() {
-                out.push_str(if b { "true" } else { "false" });
-            }
-            // functions and tables can be called, so run them later
-            else if result.is_function() || result.is_table() {
-                functions.push_back(result.clone());
-            }
+            embed(lua, result, &mut out, &mut functions)?;
         }
         // open tag and a ?fnl? parse fennel
         else if c == '<' && chars.as_str().starts_with("?") && name.has_double_ext("fnl") {
@@ -123,18 +144,7 @@ pub(crate) fn template(
                 .set_name(format!("@{name}"))
                 .eval()?;
 
-            // string, numbers or booleans can be embedded directly
-            if result.is_string() || result.is_number() || result.is_integer() {
-                out.push_str(&lua.coerce_string(result)?.unwrap().to_str()?);
-            }
-            // boolean
-            else if let Some(b) = result.as_boolean() {
-                out.push_str(if b { "true" } else { "false" });
-            }
-            // functions and tables can be called, so run them later
-            else if result.is_function() || result.is_table() {
-                functions.push_back(result.clone());
-            }
+            embed(lua, result, &mut out, &mut functions)?;
         }
         // else, simply push the character
         else {
@@ -142,11 +152,196 @@ pub(crate) fn template(
         }
     }
 
-    // unset environment
-    lua.globals().set("curfile", false)?;
-    lua.globals().set("curdir", false)?;
-    lua.globals().set("curtarget", false)?;
-    lua.globals().set("curtargetdir", false)?;
+    Ok((out, functions))
+}
 
+/// Compile the file to one function, where text is output, `<? ... ?>` is code and
+/// `<?= ... ?>` outputs the value, then run it
+/// This lets loops and conditionals span multiple blocks
+pub(crate) fn compiled(
+    lua: &Lua,
+    content: &str,
+    name: &RelativePath,
+) -> Result<(String, VecDeque<Value>)> {
+    // what the function outputs
+    let output = Rc::new(RefCell::new((String::new(), VecDeque::new())));
+    let output_clone = output.clone();
+    let emit = lua.create_function(move |lua, value: Value| {
+        let (out, functions) = &mut *output_clone.borrow_mut();
+        embed(lua, value, out, functions)
+    })?;
+
+    // run it, what it returns is embedded as well
+    let fennel = name.has_double_ext("fnl");
+    let code = compile(content, name.as_str(), fennel)?;
+    let result: Value = if fennel {
+        let name = name.as_str();
+        lua.load(chunk!(require("fennel").eval($code, { ["error-pinpoint"] = false, filename = $name }, $emit)))
+            .set_name(format!("@{name}"))
+            .eval()?
+    } else {
+        lua.load(code).set_name(format!("@{name}")).call(emit)?
+    };
+
+    let (mut out, mut functions) = output.take();
+    embed(lua, result, &mut out, &mut functions)?;
     Ok((out, functions))
 }
+
+/// Turn the file into lua or fennel code, that calls `__slsg_emit` for all output
+/// The code has the same lines as the file, so errors point to the right line
+fn compile(content: &str, name: &str, fennel: bool) -> Result<String> {
+    let mut code = String::with_capacity(content.len() * 2);
+    code.push_str(if fennel {
+        "(local __slsg_emit ...) "
+    } else {
+        "local __slsg_emit = ... "
+    });
+
+    // newlines added to end a comment, which are taken out of the text after it
+    let mut extra_lines = 0;
+    let comment = if fennel { ";" } else { "--" };
+
+    let mut rest = content;
+    while !rest.is_empty() {
+        let (text, block) = match rest.split_once("<?") {
+            Some((text, block)) => {
+                let (block, after) = block.split_once("?>").ok_or_else(|| {
+                    let start = content.len() - rest.len() + text.len();
+                    let line = content[..start].matches('\n').count() + 1;
+                    mlua::Error::external(format!("{name}:{line}: Unclosed <?"))
+                })?;
+                rest = after;
+                (text, block)
+            }
+            None => (std::mem::take(&mut rest), ""),
+        };
+
+        // text, as a string
+        if !text.is_empty() {
+            code.push_str(if fennel {
+                "(__slsg_emit \""
+            } else {
+                "__slsg_emit(\""
+            });
+            for c in text.chars() {
+                match c {
+                    '\\' => code.push_str("\\\\"),
+                    '"' => code.push_str("\\\""),
+                    '\r' => code.push_str("\\r"),
+                    '\n' if extra_lines > 0 => {
+                        code.push_str("\\n");
+                        extra_lines -= 1;
+                    }
+                    '\n' if fennel => code.push('\n'),
+                    '\n' => code.push_str("\\\n"),
+                    c => code.push(c),
+                }
+            }
+            code.push_str("\") ");
+        }
+
+        // expression, output it
+        let (block, expression) = match block.strip_prefix('=') {
+            Some(block) => (block, true),
+            None => (block, false),
+        };
+        if expression {
+            code.push_str(if fennel {
+                "(__slsg_emit "
+            } else {
+                "__slsg_emit("
+            });
+        }
+        code.push_str(block);
+
+        // end the comment on the last line, if there is one
+        if block
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .contains(comment)
+        {
+            code.push('\n');
+            extra_lines += 1;
+        }
+        code.push_str(if expression { ") " } else { " " });
+    }
+
+    Ok(code)
+}
+
+/// Embed the value in the output
+/// Strings, numbers and booleans are added directly, and functions and tables are run later
+fn embed(lua: &Lua, value: Value, out: &mut String, functions: &mut VecDeque<Value>) -> Result<()> {
+    // string, numbers or booleans can be embedded directly
+    if value.is_string() || value.is_number() || value.is_integer() {
+        out.push_str(&lua.coerce_string(value)?.unwrap().to_str()?);
+    }
+    // boolean
+    else if let Some(b) = value.as_boolean() {
+        out.push_str(if b { "true" } else { "false" });
+    }
+    // functions and tables can be called, so run them later
+    else if value.is_function() || value.is_table() {
+        functions.push_back(value);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_lua() {
+        let code = compile("a<? x = 1 ?>\"b\"<?= x ?>", "index.lua.html", false).unwrap();
+        assert_eq!(
+            code,
+            r#"local __slsg_emit = ... __slsg_emit("a")  x = 1  __slsg_emit("\"b\"") __slsg_emit( x ) "#
+        );
+    }
+
+    #[test]
+    fn compile_fennel() {
+        let code = compile("a\\<?= x ?>", "index.fnl.html", true).unwrap();
+        assert_eq!(
+            code,
+            r#"(local __slsg_emit ...) (__slsg_emit "a\\") (__slsg_emit  x ) "#
+        );
+    }
+
+    #[test]
+    fn same_lines() {
+        let content =
+            "<ul>\n<? for i = 1, 3 do -- items\n?>\n  <li><?= i ?></li>\n<? end ?>\n</ul>\n";
+        for fennel in [false, true] {
+            let code = compile(content, "index.lua.html", fennel).unwrap();
+            assert_eq!(code.matches('\n').count(), content.matches('\n').count());
+        }
+    }
+
+    #[test]
+    fn unclosed() {
+        let error = compile("a\n<? x ?>\nb <?= y\n", "index.lua.html", false).unwrap_err();
+        assert!(error.to_string().contains("index.lua.html:3: Unclosed <?"));
+    }
+
+    #[test]
+    fn mode() {
+        let rules = [
+            (None, true),
+            (Some(Pattern::new("posts/*").unwrap()), false),
+            (Some(Pattern::new("posts/list.*").unwrap()), true),
+        ];
+        assert!(compile_mode(&rules, RelativePath::new("index.lua.html")));
+        assert!(!compile_mode(&rules, RelativePath::new("posts/a.lua.md")));
+        assert!(compile_mode(
+            &rules,
+            RelativePath::new("posts/list.fnl.html")
+        ));
+        assert!(!compile_mode(&rules, RelativePath::new("style.css")));
+        assert!(!compile_mode(&[], RelativePath::new("index.lua.html")));
+    }
+}
@@ -0,0 +1,144 @@
+use mlua::{ExternalResult, Lua, Result, Table, Value};
+
+use crate::data::{Entries, Visited, create_array, entries, line_col};
+
+/// Parse toml into lua tables
+/// Dates and times are kept as strings
+pub(crate) fn parse_toml(lua: &Lua, text: &str, name: &str) -> Result<Table> {
+    let table = toml::from_str::<toml::Table>(text).map_err(|e| {
+        let (line, column) = line_col(text, e.span().map(|x| x.start).unwrap_or(0));
+        mlua::Error::external(format!("{name}:{line}:{column}: {}", e.message()))
+    })?;
+    from_toml_table(lua, table)
+}
+
+/// Turn a toml table into a lua table
+fn from_toml_table(lua: &Lua, table: toml::Table) -> Result<Table> {
+    let out = lua.create_table()?;
+    for (key, value) in table {
+        out.raw_set(key, from_toml(lua, value)?)?;
+    }
+    Ok(out)
+}
+
+/// Turn a toml value into a lua value
+fn from_toml(lua: &Lua, value: toml::Value) -> Result<Value> {
+    Ok(match value {
+        toml::Value::String(string) => Value::String(lua.create_string(string)?),
+        toml::Value::Integer(int) => Value::Integer(int),
+        toml::Value::Float(float) => Value::Number(float),
+        toml::Value::Boolean(bool) => Value::Boolean(bool),
+        toml::Value::Datetime(date) => Value::String(lua.create_string(date.to_string())?),
+        toml::Value::Array(values) => {
+            let values = values
+                .into_iter()
+                .map(|x| from_toml(lua, x))
+                .collect::<Result<Vec<_>>>()?;
+            Value::Table(create_array(lua, values)?)
+        }
+        toml::Value::Table(table) => Value::Table(from_toml_table(lua, table)?),
+    })
+}
+
+/// Serialize a lua table to toml
+/// Nil and `null` values are skipped, as toml has no null
+pub(crate) fn to_toml(lua: &Lua, table: &Table) -> Result<String> {
+    match into_toml(lua, &Value::Table(table.clone()), &mut Visited::default())? {
+        toml::Value::Table(table) => toml::to_string(&table).into_lua_err(),
+        _ => Err(mlua::Error::external(
+            "Can't serialize an array to toml, expected a table",
+        )),
+    }
+}
+
+/// Turn a lua value into a toml value
+fn into_toml(lua: &Lua, value: &Value, visited: &mut Visited) -> Result<toml::Value> {
+    Ok(match value {
+        value if value.is_null() => {
+            return Err(mlua::Error::external(
+                "Can't serialize null in an array to toml, as toml has no null",
+            ));
+        }
+        Value::Boolean(bool) => toml::Value::Boolean(*bool),
+        Value::Integer(int) => toml::Value::Integer(*int),
+        Value::Number(number) => toml::Value::Float(*number),
+        Value::String(string) => toml::Value::String(string.to_string_lossy()),
+        Value::Table(table) => {
+            visited.enter(table)?;
+            let toml = match entries(lua, table)? {
+                Entries::Array(values) => toml::Value::Array(
+                    values
+                        .iter()
+                        .map(|x| into_toml(lua, x, visited))
+                        .collect::<Result<_>>()?,
+                ),
+                Entries::Map(values) => toml::Value::Table(
+                    values
+                        .iter()
+                        .filter(|x| !x.1.is_null())
+                        .map(|(key, value)| Ok((key.clone(), into_toml(lua, value, visited)?)))
+                        .collect::<Result<_>>()?,
+                ),
+            };
+            visited.leave();
+            toml
+        }
+        value => {
+            return Err(mlua::Error::external(format!(
+                "Can't serialize a {} to toml",
+                value.type_name()
+            )));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse and serialize again
+    fn roundtrip(text: &str) -> Result<String> {
+        let lua = Lua::new();
+        to_toml(&lua, &parse_toml(&lua, text, "data.toml")?)
+    }
+
+    #[test]
+    fn tables() {
+        let text = "title = \"site\"\n[a.b]\nx = 1\n[[items]]\nname = 'one'\n[[items]]\nname = \"\"\"two\"\"\"\n";
+        assert_eq!(
+            roundtrip(text).unwrap(),
+            "title = \"site\"\n\n[a.b]\nx = 1\n\n[[items]]\nname = \"one\"\n\n[[items]]\nname = \"two\"\n"
+        );
+    }
+
+    #[test]
+    fn values() {
+        let text = "a = [1, 2.5, true]\nb = { c = 0x10, d = 1_000 }\ne = 1979-05-27\nf = inf";
+        assert_eq!(
+            roundtrip(text).unwrap(),
+            "a = [1, 2.5, true]\ne = \"1979-05-27\"\nf = inf\n\n[b]\nc = 16\nd = 1000\n"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = roundtrip("a = 1\na = 2").unwrap_err().to_string();
+        assert!(error.contains("data.toml:2:"), "{error}");
+        assert!(roundtrip("a = ").is_err());
+        assert!(roundtrip("[a\nb = 1").is_err());
+        assert!(roundtrip("a = \"unterminated").is_err());
+    }
+
+    #[test]
+    fn null() {
+        let lua = Lua::new();
+        let table = lua.create_table().unwrap();
+        table.raw_set("a", Value::NULL).unwrap();
+        table.raw_set("b", 1).unwrap();
+        assert_eq!(to_toml(&lua, &table).unwrap(), "b = 1\n");
+
+        let array = create_array(&lua, vec![Value::Integer(1), Value::Nil]).unwrap();
+        table.raw_set("c", array).unwrap();
+        assert!(to_toml(&lua, &table).is_err());
+    }
+}
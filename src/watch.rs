@@ -0,0 +1,94 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use mlua::{ErrorContext, ExternalResult};
+use relative_path::RelativePathBuf;
+
+use crate::{
+    config::Flags,
+    generate::generate,
+    print::{print_error, print_success, print_warning},
+    serve::{print_rebuilt, watch_changes},
+};
+
+/// Rebuild the site to the output directory whenever a file changes
+pub(crate) fn watch(output_path: &Path, flags: &Flags, dev: bool) -> mlua::Result<()> {
+    // time
+    let start = Instant::now();
+
+    // generate the initial site
+    let site = generate(dev, flags, Some(output_path));
+
+    // files currently on disk
+    let mut written: BTreeMap<RelativePathBuf, Vec<u8>> = BTreeMap::new();
+
+    // write it out
+    match site.and_then(|x| x.write(output_path, None).map(|_| x)) {
+        Ok(site) => written = site.files,
+        Err(e) => print_error("Failed to build site", &e),
+    }
+
+    // we are live
+    print_success(
+        &format!(
+            "watching for changes, writing to `{}` ({}ms)",
+            output_path.to_string_lossy(),
+            start.elapsed().as_millis()
+        ),
+        &"change a file to rebuild the site",
+    );
+
+    // watch for changes, but not the ones we make ourselves
+    let changed = Arc::new(AtomicBool::new(false));
+    let changed_clone = changed.clone();
+    let watcher = watch_changes(Some(output_path), move || {
+        changed_clone.store(true, Ordering::Relaxed)
+    });
+
+    // notify for an error, borrow to not drop it, as doing so would stop the watcher
+    if let Err(e) = &watcher {
+        print_warning("Failed to watch for changes", e)
+    };
+
+    // see whether we need to stop
+    let stop = Arc::new(AtomicBool::new(false));
+    let s = stop.clone();
+    ctrlc::set_handler(move || s.store(true, Ordering::Relaxed))
+        .into_lua_err()
+        .context("Failed to set stop handler")?;
+
+    // run while we are not told to stop
+    while !stop.load(Ordering::Relaxed) {
+        sleep(Duration::from_millis(100));
+
+        // rebuild if needed
+        if changed.swap(false, Ordering::Relaxed) {
+            let start = Instant::now();
+
+            // only write what changed since last time
+            let site = generate(dev, flags, Some(output_path))
+                .and_then(|x| x.write(output_path, Some(&written)).map(|_| x));
+
+            // notify how it went
+            print_rebuilt(start, &site);
+
+            // keep the files we wrote for next time
+            if let Ok(site) = site {
+                written = site.files;
+            }
+        }
+    }
+
+    // drop watcher, we are done
+    std::mem::drop(watcher);
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use relative_path::{RelativePath, RelativePathBuf};
+
+use crate::{markdown::slugify, path::Draft};
+
+/// Pages in the site and their headings, to resolve wiki links against
+#[derive(Default)]
+pub(crate) struct Wiki {
+    /// Pages, by their name and by their path
+    names: BTreeMap<String, Vec<usize>>,
+
+    /// All pages
+    pages: Vec<Page>,
+}
+
+/// Page in the wiki
+struct Page {
+    /// Where the page is emitted to
+    target: RelativePathBuf,
+
+    /// Id and text of the headings, if known
+    headings: Option<Vec<(String, String)>>,
+}
+
+impl Wiki {
+    /// Add a page, with the source path, where it is emitted to, and the id and text of its headings
+    pub(crate) fn add(
+        &mut self,
+        source: &RelativePath,
+        target: &RelativePath,
+        headings: Option<Vec<(String, String)>>,
+    ) {
+        let index = self.pages.len();
+        self.pages.push(Page {
+            target: target.to_relative_path_buf(),
+            headings,
+        });
+
+        // name without extensions, like `page` for `notes/page.lua.md`
+        let source = source.without_draft();
+        let stem = source
+            .file_name()
+            .and_then(|x| x.split('.').next())
+            .unwrap_or_default();
+        let dir = source.parent().map(key).unwrap_or_default();
+
+        let mut names = vec![slugify(stem), join(&dir, &slugify(stem))];
+
+        // index pages are also named after their directory
+        if stem == "index" && !dir.is_empty() {
+            names.push(dir.rsplit('/').next().unwrap_or_default().to_string());
+            names.push(dir);
+        }
+
+        names.dedup();
+        for name in names {
+            self.names.entry(name).or_default().push(index);
+        }
+    }
+
+    /// Url for a link like `page`, `notes/page#heading` or `#heading`, from the page at current
+    pub(crate) fn resolve(
+        &self,
+        current: &RelativePath,
+        link: &str,
+    ) -> std::result::Result<String, String> {
+        let (page, heading) = link
+            .split_once('#')
+            .map(|(x, y)| (x.trim(), Some(y.trim())))
+            .unwrap_or((link.trim(), None));
+
+        // empty page links to the current page
+        let Page { target, headings } = if page.is_empty() {
+            self.pages
+                .iter()
+                .find(|x| x.target == current)
+                .ok_or_else(|| format!("Page `{current}` is not in the wiki"))?
+        } else {
+            let name = key(RelativePath::new(page.trim_start_matches('/')));
+            match self.names.get(&name).map(Vec::as_slice) {
+                Some([index]) => &self.pages[*index],
+                Some(indices) if !indices.is_empty() => {
+                    let pages = indices
+                        .iter()
+                        .map(|x| format!("`{}`", self.pages[*x].target))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    return Err(format!(
+                        "Wiki link to `{page}` is ambiguous, it could be any of {pages}"
+                    ));
+                }
+                _ => return Err(format!("Wiki link to `{page}`, which does not exist")),
+            }
+        };
+
+        // url, without the index.html
+        let url = format!(
+            "/{}",
+            target
+                .as_str()
+                .strip_suffix("index.html")
+                .unwrap_or(target.as_str())
+        );
+
+        // find the heading, if we know them
+        match (heading, headings) {
+            (None, _) => Ok(url),
+            (Some(heading), None) => Ok(format!("{url}#{heading}")),
+            (Some(heading), Some(headings)) => headings
+                .iter()
+                .find(|(id, text)| id == heading || slugify(text) == slugify(heading))
+                .map(|(id, _)| format!("{url}#{id}"))
+                .ok_or_else(|| {
+                    format!("Wiki link to heading `{heading}` in `{target}`, which does not exist")
+                }),
+        }
+    }
+}
+
+/// Path with every part turned into a slug, so links don't depend on case or spaces
+fn key(path: &RelativePath) -> String {
+    path.components()
+        .map(|x| slugify(x.as_str()))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Join a directory and a name
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wiki() -> Wiki {
+        let mut wiki = Wiki::default();
+        let mut add = |source, target, headings: Option<&[(&str, &str)]>| {
+            let headings = headings.map(|x| {
+                x.iter()
+                    .map(|(id, text)| (id.to_string(), text.to_string()))
+                    .collect()
+            });
+            wiki.add(
+                RelativePath::new(source),
+                RelativePath::new(target),
+                headings,
+            )
+        };
+        add("index.md", "index.html", Some(&[("intro", "Intro")]));
+        add("notes/index.md", "notes/index.html", None);
+        add(
+            "notes/My Page.lua.md",
+            "notes/my-page.html",
+            Some(&[("setup-1", "Setup")]),
+        );
+        add("blog/page.md", "blog/page.html", None);
+        add("docs/page.draft.md", "docs/page.html", None);
+        wiki
+    }
+
+    #[test]
+    fn resolve() {
+        let wiki = wiki();
+        let resolve = |link| wiki.resolve(RelativePath::new("index.html"), link);
+        assert_eq!(resolve("my page").unwrap(), "/notes/my-page.html");
+        assert_eq!(
+            resolve("Notes/My Page # Setup").unwrap(),
+            "/notes/my-page.html#setup-1"
+        );
+        assert_eq!(resolve("notes").unwrap(), "/notes/");
+        assert_eq!(resolve("notes#anything").unwrap(), "/notes/#anything");
+        assert_eq!(resolve("/blog/page").unwrap(), "/blog/page.html");
+        assert_eq!(resolve("#intro").unwrap(), "/#intro");
+    }
+
+    #[test]
+    fn errors() {
+        let wiki = wiki();
+        let resolve = |link| wiki.resolve(RelativePath::new("index.html"), link);
+        assert!(resolve("page").unwrap_err().contains("ambiguous"));
+        assert!(resolve("index").unwrap_err().contains("ambiguous"));
+        assert!(resolve("missing").unwrap_err().contains("does not exist"));
+        assert!(resolve("#nope").unwrap_err().contains("does not exist"));
+        assert!(resolve("my-page#intro").is_err());
+        assert!(
+            wiki.resolve(RelativePath::new("other.html"), "#intro")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(key(RelativePath::new("Notes/My Page")), "notes/my-page");
+        assert_eq!(join("", "page"), "page");
+        assert_eq!(join("notes", "page"), "notes/page");
+    }
+}
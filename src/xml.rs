@@ -0,0 +1,291 @@
+use mlua::{ErrorContext, Lua, Result, Table, Value};
+use roxmltree::{Document, Node};
+
+use crate::data::{Entries, Visited, create_array, entries};
+
+/// Parse xml into lua tables
+/// Elements become `{ tag = ..., attrs = {...}, children = {...} }`, and text becomes strings
+pub(crate) fn parse_xml(lua: &Lua, text: &str, name: &str) -> Result<Value> {
+    let document =
+        Document::parse(text).map_err(|e| mlua::Error::external(format!("{name}: {e}")))?;
+    element(lua, document.root_element())
+}
+
+fn element(lua: &Lua, node: Node) -> Result<Value> {
+    let table = lua.create_table()?;
+    table.raw_set(
+        "tag",
+        qualified_name(node, node.tag_name().namespace(), node.tag_name().name()),
+    )?;
+
+    // namespaces declared on this element, as `xmlns` attributes
+    let attrs = lua.create_table()?;
+    let inherited = node
+        .parent()
+        .map(|x| x.namespaces().collect::<Vec<_>>())
+        .unwrap_or_default();
+    for namespace in node.namespaces() {
+        if !inherited
+            .iter()
+            .any(|x| x.name() == namespace.name() && x.uri() == namespace.uri())
+        {
+            match namespace.name() {
+                Some(name) => attrs.raw_set(format!("xmlns:{name}"), namespace.uri())?,
+                None => attrs.raw_set("xmlns", namespace.uri())?,
+            }
+        }
+    }
+
+    // attributes
+    for attr in node.attributes() {
+        attrs.raw_set(
+            qualified_name(node, attr.namespace(), attr.name()),
+            attr.value(),
+        )?;
+    }
+    table.raw_set("attrs", attrs)?;
+
+    // children, skipping whitespace on its own lines between elements
+    // whitespace in mixed content, like between words, matters
+    let mixed = node
+        .children()
+        .any(|x| x.is_text() && x.text().is_some_and(|x| !x.trim().is_empty()));
+    let mut children = Vec::new();
+    for child in node.children() {
+        if child.is_element() {
+            children.push(element(lua, child)?);
+        } else if let Some(text) = child.text().filter(|_| child.is_text())
+            && (mixed || !text.trim().is_empty() || !text.contains('\n'))
+        {
+            children.push(Value::String(lua.create_string(text)?));
+        }
+    }
+    table.raw_set("children", create_array(lua, children)?)?;
+
+    Ok(Value::Table(table))
+}
+
+/// Name with the prefix of its namespace, if it has one
+fn qualified_name(node: Node, namespace: Option<&str>, name: &str) -> String {
+    match namespace.and_then(|x| node.lookup_prefix(x)) {
+        Some(prefix) => format!("{prefix}:{name}"),
+        None => name.to_string(),
+    }
+}
+
+/// Serialize a `{ tag = ..., attrs = {...}, children = {...} }` table to xml
+/// Attributes are sorted, and elements without children are self-closing
+pub(crate) fn to_xml(lua: &Lua, value: &Value, pretty: bool) -> Result<String> {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    if pretty {
+        out.push('\n');
+    }
+    write_xml(lua, &mut out, value, pretty, 0, &mut Visited::default())?;
+    Ok(out)
+}
+
+fn write_xml(
+    lua: &Lua,
+    out: &mut String,
+    value: &Value,
+    pretty: bool,
+    depth: usize,
+    visited: &mut Visited,
+) -> Result<()> {
+    let element = match value {
+        Value::String(text) => {
+            out.push_str(&escape_xml(&text.to_string_lossy()));
+            return Ok(());
+        }
+        Value::Table(element) => element,
+        value => {
+            return Err(mlua::Error::external(format!(
+                "Can't serialize a {} to xml, expected an element or string",
+                value.type_name()
+            )));
+        }
+    };
+
+    visited.enter(element)?;
+    let tag = element
+        .get::<Option<String>>("tag")?
+        .ok_or_else(|| mlua::Error::external("Element has no `tag`"))?;
+    if !is_xml_name(&tag) {
+        return Err(mlua::Error::external(format!(
+            "Tag `{tag}` is not a valid xml name"
+        )));
+    }
+
+    // attributes
+    out.push('<');
+    out.push_str(&tag);
+    if let Some(attrs) = element.get::<Option<Table>>("attrs")?
+        && let Entries::Map(attrs) = entries(lua, &attrs)?
+    {
+        for (key, value) in attrs {
+            if !is_xml_name(&key) {
+                return Err(mlua::Error::external(format!(
+                    "Attribute `{key}` of `{tag}` is not a valid xml name"
+                )));
+            }
+            let value = value
+                .to_string()
+                .with_context(|_| format!("Attribute `{key}` of `{tag}` is not a string"))?;
+            out.push_str(&format!(" {key}=\"{}\"", escape_xml(&value)));
+        }
+    }
+
+    // children
+    let children = element
+        .get::<Option<Vec<Value>>>("children")?
+        .unwrap_or_default();
+    if children.is_empty() {
+        out.push_str("/>");
+    } else {
+        out.push('>');
+
+        // only indent if there is no text, as whitespace in text matters
+        let indent = pretty && children.iter().all(|x| x.is_table());
+        for child in &children {
+            if indent {
+                out.push('\n');
+                out.push_str(&"  ".repeat(depth + 1));
+            }
+            write_xml(lua, out, child, pretty, depth + 1, visited)?;
+        }
+        if indent {
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth));
+        }
+
+        out.push_str(&format!("</{tag}>"));
+    }
+    visited.leave();
+
+    Ok(())
+}
+
+/// Whether the text can be used as a tag or attribute name
+/// See <https://www.w3.org/TR/xml/#NT-Name>
+fn is_xml_name(name: &str) -> bool {
+    let start = |c: char| {
+        matches!(c,
+            ':' | 'A'..='Z' | '_' | 'a'..='z' | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}'
+            | '\u{F8}'..='\u{2FF}' | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}'
+            | '\u{200C}'..='\u{200D}' | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}'
+            | '\u{3001}'..='\u{D7FF}' | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}'
+            | '\u{10000}'..='\u{EFFFF}')
+    };
+    let rest = |c: char| {
+        start(c)
+            || matches!(c,
+                '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+    };
+
+    let mut chars = name.chars();
+    chars.next().is_some_and(start) && chars.all(rest)
+}
+
+/// Escape text and attributes
+fn escape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::to_json;
+
+    #[test]
+    fn roundtrip() {
+        let lua = Lua::new();
+        let text = r#"<feed b="2" a="&quot;1&quot;">
+            <title>A &amp; B</title>
+            <link href="/"/>
+        </feed>"#;
+        let value = parse_xml(&lua, text, "feed.xml").unwrap();
+        assert_eq!(
+            to_xml(&lua, &value, false).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><feed a="&quot;1&quot;" b="2"><title>A &amp; B</title><link href="/"/></feed>"#
+        );
+        assert_eq!(
+            to_xml(&lua, &value, true).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed a=\"&quot;1&quot;\" b=\"2\">\n  <title>A &amp; B</title>\n  <link href=\"/\"/>\n</feed>"
+        );
+    }
+
+    #[test]
+    fn namespaces() {
+        let lua = Lua::new();
+        let text = concat!(
+            r#"<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">"#,
+            r#"<media:thumbnail url="a.png" xml:lang="en"/><p><b>a</b> <i>b</i></p></feed>"#
+        );
+        let value = parse_xml(&lua, text, "feed.xml").unwrap();
+        assert_eq!(
+            to_xml(&lua, &value, false).unwrap(),
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">"#,
+                r#"<media:thumbnail url="a.png" xml:lang="en"/><p><b>a</b> <i>b</i></p></feed>"#
+            )
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let lua = Lua::new();
+        assert!(parse_xml(&lua, "<a><b></a>", "data.xml").is_err());
+        assert!(to_xml(&lua, &Value::Integer(1), false).is_err());
+
+        let element = lua.create_table().unwrap();
+        assert!(to_xml(&lua, &Value::Table(element.clone()), false).is_err());
+
+        // elements containing themselves
+        element.raw_set("tag", "a").unwrap();
+        let children = create_array(&lua, vec![Value::Table(element.clone())]).unwrap();
+        element.raw_set("children", children).unwrap();
+        assert!(to_xml(&lua, &Value::Table(element.clone()), false).is_err());
+        assert!(to_json(&lua, &Value::Table(element), false).is_err());
+
+        // names that aren't xml names
+        let element = lua
+            .load(r#"{ tag = "a b", attrs = {}, children = {} }"#)
+            .eval::<Value>()
+            .unwrap();
+        let error = to_xml(&lua, &element, false).unwrap_err().to_string();
+        assert!(
+            error.contains("Tag `a b` is not a valid xml name"),
+            "{error}"
+        );
+        let element = lua
+            .load(r#"{ tag = "a", attrs = { ["x><script"] = "1" } }"#)
+            .eval::<Value>()
+            .unwrap();
+        let error = to_xml(&lua, &element, false).unwrap_err().to_string();
+        assert!(
+            error.contains("Attribute `x><script` of `a` is not a valid xml name"),
+            "{error}"
+        );
+        assert!(is_xml_name("media:thumbnail") && is_xml_name("_a-1.b"));
+        assert!(!is_xml_name("") && !is_xml_name("1a") && !is_xml_name("-a"));
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            escape_xml("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;'"
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use mlua::{Lua, Result, Table, Value};
+use yaml_rust2::{
+    Event, Yaml,
+    parser::{MarkedEventReceiver, Parser, Tag},
+    scanner::{Marker, TScalarStyle},
+};
+
+use crate::data::{create_array, is_array};
+
+/// Parse yaml into lua values
+/// Only the first document is read, and tags other than `!!str` are ignored
+pub(crate) fn parse_yaml(lua: &Lua, text: &str, name: &str) -> Result<Value> {
+    let mut builder = Builder {
+        lua,
+        name,
+        stack: Vec::new(),
+        anchors: BTreeMap::new(),
+        value: None,
+        error: None,
+    };
+
+    Parser::new_from_str(text)
+        .load(&mut builder, false)
+        .map_err(|e| error(name, e.marker(), e.info()))?;
+
+    match builder.error {
+        Some(error) => Err(error),
+        None => Ok(builder.value.unwrap_or(Value::Nil)),
+    }
+}
+
+/// Collection that is being built
+enum Node {
+    Sequence {
+        anchor: usize,
+        values: Vec<Value>,
+    },
+    Mapping {
+        anchor: usize,
+        table: Table,
+
+        /// Keys seen so far, to catch duplicates
+        keys: BTreeSet<String>,
+
+        /// Key waiting for its value, and whether it's a `<<` merge key
+        key: Option<(String, Marker, bool)>,
+
+        /// Tables from `<<` keys, added after all other keys
+        merges: Vec<Table>,
+    },
+}
+
+/// Builds lua values from the parser events
+struct Builder<'a> {
+    lua: &'a Lua,
+    name: &'a str,
+    stack: Vec<Node>,
+    anchors: BTreeMap<usize, Value>,
+
+    /// The document, once it's done
+    value: Option<Value>,
+
+    /// First error, as the parser can't be stopped
+    error: Option<mlua::Error>,
+}
+
+impl MarkedEventReceiver for Builder<'_> {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        if self.error.is_none()
+            && let Err(error) = self.event(event, mark)
+        {
+            self.error = Some(error);
+        }
+    }
+}
+
+impl Builder<'_> {
+    fn event(&mut self, event: Event, mark: Marker) -> Result<()> {
+        match event {
+            Event::SequenceStart(anchor, _) => self.stack.push(Node::Sequence {
+                anchor,
+                values: Vec::new(),
+            }),
+            Event::MappingStart(anchor, _) => self.stack.push(Node::Mapping {
+                anchor,
+                table: self.lua.create_table()?,
+                keys: BTreeSet::new(),
+                key: None,
+                merges: Vec::new(),
+            }),
+            Event::SequenceEnd => {
+                let Some(Node::Sequence { anchor, values }) = self.stack.pop() else {
+                    unreachable!("Sequences end after they start")
+                };
+                let value = Value::Table(create_array(self.lua, values)?);
+                self.insert(anchor, value, None, mark)?;
+            }
+            Event::MappingEnd => {
+                let Some(Node::Mapping {
+                    anchor,
+                    table,
+                    merges,
+                    ..
+                }) = self.stack.pop()
+                else {
+                    unreachable!("Mappings end after they start")
+                };
+
+                // merged keys don't override keys in the mapping, and earlier merges win
+                for merge in merges {
+                    for pair in merge.pairs::<Value, Value>() {
+                        let (key, value) = pair?;
+                        if table.raw_get::<Value>(&key)?.is_nil() {
+                            table.raw_set(key, value)?;
+                        }
+                    }
+                }
+
+                self.insert(anchor, Value::Table(table), None, mark)?;
+            }
+            Event::Scalar(text, style, anchor, tag) => {
+                let value = self.scalar(&text, style, tag.as_ref())?;
+                let raw = (style == TScalarStyle::Plain && tag.is_none()).then_some(text);
+                self.insert(anchor, value, raw, mark)?;
+            }
+            Event::Alias(anchor) => {
+                let value =
+                    self.anchors.get(&anchor).cloned().ok_or_else(|| {
+                        error(self.name, &mark, "Alias to the collection it's in")
+                    })?;
+                self.insert(0, value, None, mark)?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Value of a scalar, quoted ones and `!!str` are always strings
+    fn scalar(&self, text: &str, style: TScalarStyle, tag: Option<&Tag>) -> Result<Value> {
+        let string = style != TScalarStyle::Plain
+            || tag.is_some_and(|x| x.handle == "tag:yaml.org,2002:" && x.suffix == "str");
+        if string {
+            return self.lua.create_string(text).map(Value::String);
+        }
+
+        Ok(match Yaml::from_str(text) {
+            Yaml::Integer(int) => Value::Integer(int),
+            real @ Yaml::Real(_) => Value::Number(real.as_f64().unwrap_or(f64::NAN)),
+            Yaml::Boolean(bool) => Value::Boolean(bool),
+            Yaml::Null => Value::Nil,
+            _ => Value::String(self.lua.create_string(text)?),
+        })
+    }
+
+    /// Add a finished value to the collection it's in
+    /// Raw is the text of plain scalars, which can be `<<` for merge keys
+    fn insert(
+        &mut self,
+        anchor: usize,
+        value: Value,
+        raw: Option<String>,
+        mark: Marker,
+    ) -> Result<()> {
+        if anchor > 0 {
+            self.anchors.insert(anchor, value.clone());
+        }
+
+        let name = self.name;
+        match self.stack.last_mut() {
+            None => self.value = Some(value),
+            Some(Node::Sequence { values, .. }) => values.push(value),
+            Some(Node::Mapping {
+                table,
+                keys,
+                key,
+                merges,
+                ..
+            }) => match key.take() {
+                // key, as a string
+                None => {
+                    let key_text = match (&raw, &value) {
+                        (Some(raw), _) => raw.clone(),
+                        (None, Value::String(string)) => string.to_str()?.to_string(),
+                        (None, Value::Integer(_) | Value::Number(_) | Value::Boolean(_)) => {
+                            value.to_string()?
+                        }
+                        _ => return Err(error(name, &mark, "Keys can't be collections or null")),
+                    };
+                    let merge = raw.as_deref() == Some("<<");
+                    *key = Some((key_text, mark, merge));
+                }
+                // merge key, add the tables later
+                Some((_, _, true)) => {
+                    let Value::Table(merge) = value else {
+                        return Err(error(name, &mark, "Expected a mapping to merge"));
+                    };
+                    if is_array(self.lua, &merge)? {
+                        for item in merge.sequence_values::<Value>() {
+                            let Value::Table(item) = item? else {
+                                return Err(error(name, &mark, "Expected a mapping to merge"));
+                            };
+                            merges.push(item);
+                        }
+                    } else {
+                        merges.push(merge);
+                    }
+                }
+                // value
+                Some((key, key_mark, false)) => {
+                    if !keys.insert(key.clone()) {
+                        return Err(error(name, &key_mark, &format!("Duplicate key `{key}`")));
+                    }
+                    table.raw_set(key, value)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// Error at the line of the mark
+fn error(name: &str, mark: &Marker, message: &str) -> mlua::Error {
+    mlua::Error::external(format!("{name}:{}: {message}", mark.line()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::to_json;
+
+    /// Parse and serialize to json, to compare
+    fn to_json_text(text: &str) -> Result<String> {
+        let lua = Lua::new();
+        let value = parse_yaml(&lua, text, "data.yaml")?;
+        to_json(&lua, &value, false)
+    }
+
+    #[test]
+    fn collections() {
+        let text = "a: 1\nb:\n- x\n- [1, 2.5, ~]\nc: {d: true, e: 'f'}\n";
+        assert_eq!(
+            to_json_text(text).unwrap(),
+            r#"{"a":1,"b":["x",[1,2.5,null]],"c":{"d":true,"e":"f"}}"#
+        );
+        assert_eq!(
+            to_json_text("- a: 1\n  b: 2\n-\n  - c\n").unwrap(),
+            r#"[{"a":1,"b":2},["c"]]"#
+        );
+        assert_eq!(
+            to_json_text("1: a\ntrue: b\n").unwrap(),
+            r#"{"1":"a","true":"b"}"#
+        );
+    }
+
+    #[test]
+    fn scalars() {
+        let text =
+            "a: |\n  one\n  two\nb: >-\n  one\n  two\nc: \"x\\ty\"\nd: plain\n  text\ne: '12'\n";
+        assert_eq!(
+            to_json_text(text).unwrap(),
+            r#"{"a":"one\ntwo\n","b":"one two","c":"x\ty","d":"plain text","e":"12"}"#
+        );
+    }
+
+    #[test]
+    fn anchors_and_aliases() {
+        let text =
+            "base: &base\n  a: 1\n  b: 2\nitem: &x [1, 2]\ncopy: *x\nother:\n  <<: *base\n  b: 3\n";
+        assert_eq!(
+            to_json_text(text).unwrap(),
+            r#"{"base":{"a":1,"b":2},"copy":[1,2],"item":[1,2],"other":{"a":1,"b":3}}"#
+        );
+        assert_eq!(
+            to_json_text("- &a x\n- {b: *a}\n").unwrap(),
+            r#"["x",{"b":"x"}]"#
+        );
+        assert_eq!(
+            to_json_text("- &a {a: 1}\n- &b {a: 2, b: 2}\n- <<: [*a, *b]\n").unwrap(),
+            r#"[{"a":1},{"a":2,"b":2},{"a":1,"b":2}]"#
+        );
+        let error = to_json_text("a: 1\nb: *missing").unwrap_err().to_string();
+        assert!(error.contains("data.yaml:2: "), "{error}");
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(
+            to_json_text("a: !!str 12\nb: !custom 12\nc: !!str true\n").unwrap(),
+            r#"{"a":"12","b":12,"c":"true"}"#
+        );
+    }
+
+    #[test]
+    fn documents() {
+        assert_eq!(to_json_text("--- 1\n--- 2\n").unwrap(), "1");
+        assert!(parse_yaml(&Lua::new(), "", "data.yaml").unwrap().is_nil());
+    }
+
+    #[test]
+    fn errors() {
+        let error = to_json_text("a: 1\nb: 2\na: 3").unwrap_err().to_string();
+        assert!(error.contains("data.yaml:3: Duplicate key `a`"), "{error}");
+        assert!(to_json_text("a: [1, 2").is_err());
+        assert!(to_json_text("a:\n\t- 1").is_err());
+        assert!(to_json_text("a: 'unterminated").is_err());
+        assert!(to_json_text("[a]: 1").is_err());
+    }
+}